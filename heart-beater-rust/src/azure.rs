@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use object_store::azure::MicrosoftAzureBuilder;
+
+use crate::{bucket::ObjectStoreCheck, config::ConfigAzurePing};
+
+pub fn new_check(config: &ConfigAzurePing) -> Result<ObjectStoreCheck> {
+    let store = Arc::new(
+        MicrosoftAzureBuilder::from_env()
+            .with_account(&config.account)
+            .with_container_name(&config.container)
+            .with_use_emulator(config.use_emulator)
+            .build()?,
+    );
+
+    Ok(ObjectStoreCheck::new(
+        "azure",
+        config.label(),
+        store,
+        config.freshness.clone(),
+    ))
+}
@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use log::debug;
+use object_store::{path::Path, ObjectStore};
+
+use crate::{
+    check::{Check, CheckOutcome, Observation},
+    config::ConfigFreshness,
+};
+
+/// The newest object found under a prefix, independent of the storage backend.
+#[derive(Debug)]
+pub struct LatestObject {
+    pub key: String,
+    pub size: Option<u64>,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+impl LatestObject {
+    pub fn observe(&self, observation: &mut Observation) {
+        observation.object_key = Some(self.key.clone());
+        observation.object_size = self.size;
+        if let Some(last_modified) = self.last_modified {
+            observation.set_age(last_modified);
+        }
+    }
+}
+
+pub fn check_freshness(object: &LatestObject, freshness: &ConfigFreshness) -> Result<()> {
+    if let Some(min_size) = freshness.min_size.as_ref() {
+        let size = object
+            .size
+            .ok_or(anyhow::anyhow!("key={:?} no size found", object.key))?;
+        if size < *min_size {
+            return Err(anyhow::anyhow!(
+                "key={:?} size {size} is smaller than {min_size}",
+                object.key
+            ));
+        }
+    }
+    let at = object
+        .last_modified
+        .ok_or(anyhow::anyhow!(
+            "key={:?} no last_modified found",
+            object.key
+        ))?
+        .timestamp_millis();
+
+    if at + freshness.grace.as_millis() as i64 > Utc::now().timestamp_millis() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "key={:?} is older than {:?}",
+            object.key,
+            freshness.grace
+        ))
+    }
+}
+
+/// `object_store` lists by path segment, so list the parent directory and
+/// filter by the raw prefix to keep the same semantics as S3 `ListObjectsV2`.
+async fn get_latest_object(store: &dyn ObjectStore, prefix: &str) -> Result<Option<LatestObject>> {
+    let parent = prefix
+        .rsplit_once('/')
+        .map(|(parent, _)| Path::from(parent));
+    let mut objects = store.list(parent.as_ref());
+
+    let mut latest_object: Option<LatestObject> = None;
+    while let Some(meta) = objects.try_next().await? {
+        debug!("get object => {meta:?}");
+        if !meta.location.as_ref().starts_with(prefix) {
+            continue;
+        }
+        if latest_object
+            .as_ref()
+            .and_then(|lo| lo.last_modified)
+            .is_none_or(|dt| meta.last_modified > dt)
+        {
+            latest_object = Some(LatestObject {
+                key: meta.location.to_string(),
+                size: Some(meta.size),
+                last_modified: Some(meta.last_modified),
+            });
+        }
+    }
+
+    Ok(latest_object)
+}
+
+async fn tick(
+    store: &dyn ObjectStore,
+    freshness: &ConfigFreshness,
+    observation: &mut Observation,
+) -> Result<()> {
+    match get_latest_object(store, &freshness.prefix).await? {
+        Some(object) => {
+            debug!("latest object => {object:?} / config={freshness:?}");
+            object.observe(observation);
+            check_freshness(&object, freshness)
+        }
+        None => Err(anyhow::anyhow!("not data found. or failed to access")),
+    }
+}
+
+/// Freshness check backed by any `object_store` implementation (GCS, Azure, ...).
+pub struct ObjectStoreCheck {
+    kind: &'static str,
+    label: String,
+    store: Arc<dyn ObjectStore>,
+    freshness: ConfigFreshness,
+}
+
+impl ObjectStoreCheck {
+    pub fn new(
+        kind: &'static str,
+        label: String,
+        store: Arc<dyn ObjectStore>,
+        freshness: ConfigFreshness,
+    ) -> Self {
+        Self {
+            kind,
+            label,
+            store,
+            freshness,
+        }
+    }
+}
+
+impl Check for ObjectStoreCheck {
+    fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    fn label(&self) -> String {
+        self.label.clone()
+    }
+
+    async fn run(&self) -> CheckOutcome {
+        let mut observation = Observation::default();
+        let result = tick(self.store.as_ref(), &self.freshness, &mut observation).await;
+        CheckOutcome::from(result).with_observation(observation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn latest_object_with_raw_prefix() {
+        let store = InMemory::new();
+        for key in [
+            "hoge/backup-1.tar",
+            "hoge/backup-2.tar",
+            "hoge/other.tar",
+            "fuga/backup-3.tar",
+        ] {
+            store
+                .put(&Path::from(key), vec![0u8; 10].into())
+                .await
+                .unwrap();
+        }
+
+        let latest = get_latest_object(&store, "hoge/backup-")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.key, "hoge/backup-2.tar");
+        assert_eq!(latest.size, Some(10));
+
+        assert!(get_latest_object(&store, "piyo/").await.unwrap().is_none());
+    }
+}
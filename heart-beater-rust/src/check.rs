@@ -0,0 +1,106 @@
+use std::future::Future;
+
+use chrono::{DateTime, Utc};
+
+/// Values a check measured, reported whether it passed or not.
+#[derive(Debug, Default, Clone)]
+pub struct Observation {
+    pub status_code: Option<u16>,
+    pub object_key: Option<String>,
+    pub object_size: Option<u64>,
+    pub value: Option<String>,
+    /// Seconds since the observed object or timestamp was written.
+    pub age_secs: Option<u64>,
+}
+
+impl Observation {
+    /// Timestamps in the future count as age zero.
+    pub fn set_age(&mut self, at: DateTime<Utc>) {
+        self.age_secs = Some((Utc::now() - at).num_seconds().max(0) as u64);
+    }
+}
+
+/// Result of a single run of a [`Check`].
+#[derive(Debug)]
+pub struct CheckOutcome {
+    pub observation: Observation,
+    /// `Ok` when the check passed.
+    pub result: anyhow::Result<()>,
+}
+
+impl CheckOutcome {
+    pub fn ok() -> Self {
+        Self {
+            observation: Observation::default(),
+            result: Ok(()),
+        }
+    }
+
+    pub fn fail(err: impl Into<anyhow::Error>) -> Self {
+        Self {
+            observation: Observation::default(),
+            result: Err(err.into()),
+        }
+    }
+
+    pub fn with_observation(self, observation: Observation) -> Self {
+        Self {
+            observation,
+            ..self
+        }
+    }
+}
+
+impl From<anyhow::Result<()>> for CheckOutcome {
+    fn from(result: anyhow::Result<()>) -> Self {
+        Self {
+            observation: Observation::default(),
+            result,
+        }
+    }
+}
+
+/// Something that can be probed on a schedule.
+///
+/// The scheduler takes care of jitter, concurrency limits, thresholds,
+/// heartbeat / fail pings and logging; a check only has to say whether it
+/// passed.
+///
+/// ```no_run
+/// use heart_beater_rust::{config::ConfigCheck, Check, CheckOutcome, Scheduler};
+///
+/// struct QueueDepth;
+///
+/// impl Check for QueueDepth {
+///     fn label(&self) -> String {
+///         "queue-depth".into()
+///     }
+///
+///     async fn run(&self) -> CheckOutcome {
+///         CheckOutcome::ok()
+///     }
+/// }
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let scheduler = Scheduler::builder()
+///     .check(
+///         ConfigCheck::new("0 * * * * *", "https://heartbeat.com"),
+///         QueueDepth,
+///     )
+///     .build()
+///     .await?;
+/// scheduler.start().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait Check: Send + Sync + 'static {
+    /// Reported as `kind` in the tick event.
+    fn kind(&self) -> &'static str {
+        "custom"
+    }
+
+    /// Name used when [`ConfigCheck::name`](crate::config::ConfigCheck::name) is not set.
+    fn label(&self) -> String;
+
+    fn run(&self) -> impl Future<Output = CheckOutcome> + Send;
+}
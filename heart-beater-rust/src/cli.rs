@@ -1,63 +1,141 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{path::PathBuf, time::Duration};
 
 use anyhow::Result;
-use aws_config::Region;
 use clap::Parser;
-use log::{debug, info};
-use tokio::signal::unix::SignalKind;
-use tokio_cron_scheduler::JobScheduler;
+use log::{debug, error, info};
 
-use super::config::Config;
+use super::{
+    config::Config,
+    logging::{self, LogFormat},
+    push,
+    scheduler::Scheduler,
+};
 
 #[derive(Parser)]
 struct Args {
+    /// Can be given multiple times; all files are merged.
     #[arg(short, long)]
-    config_filename: String,
-}
+    config_filename: Vec<PathBuf>,
 
-pub async fn main() -> Result<()> {
-    env_logger::init();
-    let args = Args::parse();
+    /// Loads every `*.yaml` / `*.yml` in this directory, in file name order.
+    #[arg(long)]
+    config_dir: Option<PathBuf>,
 
-    let config: Config = {
-        let config_str = std::io::read_to_string(std::fs::File::open(&args.config_filename)?)?;
-        let yaml = serde_yaml::yaml_from_str(&config_str)?;
-        assert!(yaml.len() == 1);
-        serde_yaml::from_yaml(&yaml[0])?
-    };
+    #[arg(long, value_enum, env = "LOG_FORMAT", default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
 
-    let sched = JobScheduler::new().await?;
+    /// How long to wait for running checks on SIGTERM / SIGINT / reload.
+    #[arg(long, value_parser = parse_duration::parse, default_value = "30s")]
+    shutdown_timeout: Duration,
+}
 
-    if let Some(http_list) = config.http {
-        for http_config in http_list {
-            debug!("http => {http_config:?}");
-            super::http::add_job(&sched, http_config).await?;
+impl Args {
+    /// Listed again on every reload so that files added to `config_dir` are picked up.
+    fn config_paths(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = self.config_filename.clone();
+        if let Some(config_dir) = self.config_dir.as_ref() {
+            let mut dir_paths = std::fs::read_dir(config_dir)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            dir_paths.retain(|path| {
+                path.is_file()
+                    && path
+                        .extension()
+                        .is_some_and(|ext| ext == "yaml" || ext == "yml")
+            });
+            dir_paths.sort();
+            paths.extend(dir_paths);
+        }
+        if paths.is_empty() {
+            return Err(anyhow::anyhow!(
+                "no config given. use --config-filename or --config-dir"
+            ));
         }
+        Ok(paths)
+    }
+
+    fn load_config(&self) -> Result<Config> {
+        let paths = self.config_paths()?;
+        debug!("config files => {paths:?}");
+        Config::load(&paths)
     }
+}
+
+/// `push_pings` carries the last push pings over from the replaced scheduler.
+async fn start(config: Config, push_pings: Option<push::Registry>) -> Result<Scheduler> {
+    let scheduler = Scheduler::builder()
+        .config(config)
+        .push_pings(push_pings)
+        .build()
+        .await?;
+    scheduler.start().await?;
+    Ok(scheduler)
+}
+
+/// Stops the current set of checks and starts the new one. When the new one
+/// fails to start, the previous config is started again. Failures are only
+/// logged so that a bad reload never ends the daemon.
+async fn reload(running: &mut Option<Scheduler>, current: &mut Config, args: &Args) {
+    let config = match args.load_config() {
+        Ok(config) => config,
+        Err(err) => {
+            error!("failed to reload config, keep running the current one {err:?}");
+            return;
+        }
+    };
 
-    if let Some(s3_list) = config.s3 {
-        if !s3_list.is_empty() {
-            let mut client_map: HashMap<String, Arc<aws_sdk_s3::Client>> = HashMap::new();
-
-            for s3_config in s3_list {
-                let client = if let Some(client) = client_map.get(&s3_config.region) {
-                    client.clone()
-                } else {
-                    let region = Region::new(s3_config.region.clone());
-                    let sdk_config = aws_config::from_env().region(region).load().await;
-                    let client = Arc::new(aws_sdk_s3::Client::new(&sdk_config));
-                    client_map.insert(s3_config.region.clone(), client.clone());
-                    client
-                };
-
-                debug!("s3 => {s3_config:?}");
-                super::s3::add_job(&sched, client.clone(), s3_config).await?;
+    let push_pings = running
+        .as_ref()
+        .and_then(|scheduler| scheduler.push_pings());
+    if let Some(scheduler) = running.as_mut() {
+        if let Err(err) = scheduler.shutdown(args.shutdown_timeout).await {
+            error!("failed to stop the current checks, keep running them {err:?}");
+            return;
+        }
+        *running = None;
+    }
+    match start(config.clone(), push_pings.clone()).await {
+        Ok(scheduler) => {
+            info!("config reloaded");
+            *current = config;
+            *running = Some(scheduler);
+        }
+        Err(err) => {
+            error!("failed to start reloaded config, restore the previous one {err:?}");
+            match start(current.clone(), push_pings).await {
+                Ok(scheduler) => *running = Some(scheduler),
+                Err(err) => {
+                    error!("failed to restore the previous config, no checks run until the next reload {err:?}")
+                }
             }
         }
     }
+}
 
-    sched.shutdown_on_signal(SignalKind::terminate());
-    sched.start().await?;
+/// One `check status` event per check, see the README.
+fn log_status(running: Option<&Scheduler>) {
+    let Some(running) = running else {
+        info!("no checks running");
+        return;
+    };
+    for status in running.status() {
+        info!(
+            target: "status",
+            check = status.name.as_str(),
+            healthy = status.healthy,
+            consecutive_successes = status.consecutive_successes,
+            consecutive_failures = status.consecutive_failures;
+            "check status"
+        );
+    }
+}
+
+pub async fn main() -> Result<()> {
+    let args = Args::parse();
+    logging::init(args.log_format);
+
+    let mut config = args.load_config()?;
+    let mut running = Some(start(config.clone(), None).await?);
     info!("running loop");
     {
         use tokio::signal::{
@@ -67,13 +145,29 @@ pub async fn main() -> Result<()> {
 
         let mut sig_int = signal(SignalKind::interrupt()).unwrap();
         let mut sig_term = signal(SignalKind::terminate()).unwrap();
-        tokio::select! {
-            _ = sig_int.recv() => debug!("SIGINT received"),
-            _ = sig_term.recv() => debug!("SIGTERM received"),
-            _ = ctrl_c() => debug!("'Ctrl C' received"),
+        let mut sig_hup = signal(SignalKind::hangup()).unwrap();
+        let mut sig_usr1 = signal(SignalKind::user_defined1()).unwrap();
+        loop {
+            tokio::select! {
+                _ = sig_hup.recv() => {
+                    debug!("SIGHUP received");
+                    reload(&mut running, &mut config, &args).await;
+                }
+                _ = sig_usr1.recv() => {
+                    debug!("SIGUSR1 received");
+                    log_status(running.as_ref());
+                }
+                _ = sig_int.recv() => { debug!("SIGINT received"); break; }
+                _ = sig_term.recv() => { debug!("SIGTERM received"); break; }
+                _ = ctrl_c() => { debug!("'Ctrl C' received"); break; }
+            }
         }
     }
 
+    info!("shutting down, waiting for running checks");
+    if let Some(mut running) = running {
+        running.shutdown(args.shutdown_timeout).await?;
+    }
     info!("program terminated");
 
     Ok(())
@@ -1,31 +1,385 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use anyhow::Result;
 use serde::Deserialize;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, Default)]
 pub struct Config {
+    pub max_concurrency: Option<usize>,
     pub http: Option<Vec<ConfigHttpPing>>,
     pub s3: Option<Vec<ConfigS3Ping>>,
+    pub gcs: Option<Vec<ConfigGcsPing>>,
+    pub azure: Option<Vec<ConfigAzurePing>>,
+    pub push: Option<ConfigPush>,
+    pub sql: Option<Vec<ConfigSqlPing>>,
+    pub sftp: Option<Vec<ConfigSftpPing>>,
+}
+
+impl Config {
+    /// Loads every file and merges them into one set of checks.
+    pub fn load(paths: &[PathBuf]) -> Result<Config> {
+        let mut config = Config::default();
+        for path in paths {
+            let config_str = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("failed to read {path:?}. {e}"))?;
+            let yaml = serde_yaml::yaml_from_str(&config_str)
+                .map_err(|e| anyhow::anyhow!("failed to parse {path:?}. {e}"))?;
+            let [yaml] = &yaml[..] else {
+                return Err(anyhow::anyhow!(
+                    "{path:?} must contain exactly one YAML document, found {}",
+                    yaml.len()
+                ));
+            };
+            let other: Config = serde_yaml::from_yaml(yaml)
+                .map_err(|e| anyhow::anyhow!("failed to parse {path:?}. {e}"))?;
+            config
+                .merge(other)
+                .map_err(|e| anyhow::anyhow!("failed to merge {path:?}. {e}"))?;
+        }
+        config.check_duplicate_names()?;
+        Ok(config)
+    }
+
+    fn merge(&mut self, other: Config) -> Result<()> {
+        fn extend<T>(list: &mut Option<Vec<T>>, other: Option<Vec<T>>) {
+            if let Some(other) = other {
+                list.get_or_insert_with(Vec::new).extend(other);
+            }
+        }
+
+        self.max_concurrency = match (self.max_concurrency, other.max_concurrency) {
+            (Some(a), Some(b)) if a != b => {
+                return Err(anyhow::anyhow!("max_concurrency {a} conflicts with {b}"))
+            }
+            (a, b) => a.or(b),
+        };
+        extend(&mut self.http, other.http);
+        extend(&mut self.s3, other.s3);
+        extend(&mut self.gcs, other.gcs);
+        extend(&mut self.azure, other.azure);
+        extend(&mut self.sql, other.sql);
+        extend(&mut self.sftp, other.sftp);
+        self.push = match (self.push.take(), other.push) {
+            (Some(mut a), Some(b)) => {
+                if a.bind != b.bind {
+                    return Err(anyhow::anyhow!(
+                        "push.bind {} conflicts with {}",
+                        a.bind,
+                        b.bind
+                    ));
+                }
+                a.checks.extend(b.checks);
+                Some(a)
+            }
+            (a, b) => a.or(b),
+        };
+        Ok(())
+    }
+
+    /// Compares the names checks run under: `name`, or the label of their target.
+    fn check_duplicate_names(&self) -> Result<()> {
+        fn effective(check: &ConfigCheck, label: String) -> String {
+            check.name.clone().unwrap_or(label)
+        }
+        let names = self
+            .http
+            .iter()
+            .flatten()
+            .map(|c| effective(&c.check, c.label()))
+            .chain(
+                self.s3
+                    .iter()
+                    .flatten()
+                    .map(|c| effective(&c.check, c.label())),
+            )
+            .chain(
+                self.gcs
+                    .iter()
+                    .flatten()
+                    .map(|c| effective(&c.check, c.label())),
+            )
+            .chain(
+                self.azure
+                    .iter()
+                    .flatten()
+                    .map(|c| effective(&c.check, c.label())),
+            )
+            .chain(
+                self.sql
+                    .iter()
+                    .flatten()
+                    .map(|c| effective(&c.check, c.label())),
+            )
+            .chain(
+                self.sftp
+                    .iter()
+                    .flatten()
+                    .map(|c| effective(&c.check, c.label())),
+            )
+            .chain(
+                self.push
+                    .iter()
+                    .flat_map(|p| p.checks.iter().map(|c| effective(&c.check, c.label()))),
+            );
+
+        let mut seen = HashSet::new();
+        for name in names {
+            if seen.contains(&name) {
+                return Err(anyhow::anyhow!("duplicate check name {name:?}"));
+            }
+            seen.insert(name);
+        }
+        Ok(())
+    }
+}
+
+impl ConfigHttpPing {
+    /// Name of the check when `name` is omitted.
+    pub fn label(&self) -> String {
+        self.target_url.clone()
+    }
+}
+
+impl ConfigS3Ping {
+    pub fn label(&self) -> String {
+        format!("s3://{}/{}", self.bucket, self.freshness.prefix)
+    }
+}
+
+impl ConfigGcsPing {
+    pub fn label(&self) -> String {
+        format!("gs://{}/{}", self.bucket, self.freshness.prefix)
+    }
+}
+
+impl ConfigAzurePing {
+    pub fn label(&self) -> String {
+        format!(
+            "az://{}/{}/{}",
+            self.account, self.container, self.freshness.prefix
+        )
+    }
+}
+
+impl ConfigSftpPing {
+    pub fn label(&self) -> String {
+        let path = std::path::Path::new(&self.directory).join(&self.glob);
+        // an absolute directory already starts with the separator
+        let separator = if path.is_absolute() { "" } else { "/" };
+        format!(
+            "sftp://{}@{}:{}{separator}{}",
+            self.user,
+            self.host,
+            self.port,
+            path.display()
+        )
+    }
 }
 
+impl ConfigSqlPing {
+    pub fn label(&self) -> String {
+        format!("sql/{}", self.dsn_env)
+    }
+}
+
+impl ConfigPushPing {
+    pub fn label(&self) -> String {
+        format!("push/{}", self.name)
+    }
+}
+
+/// Scheduling and notification settings shared by every kind of check.
 #[derive(Debug, Deserialize, Clone)]
-pub struct ConfigHttpPing {
-    pub target_url: String,
+pub struct ConfigCheck {
+    /// Shown in logs. Defaults to the target URL or object store location.
+    pub name: Option<String>,
     pub cron: String,
+    /// May contain `{placeholder}`s, see `heartbeat_body`; values are URL-encoded.
     pub heartbeat_url: String,
+    /// When set, the heartbeat is a POST with this body. `{latency_ms}`,
+    /// `{status_code}`, `{object_key}`, `{object_size}`, `{age_secs}`, `{value}`,
+    /// `{check}`, `{kind}` and `{outcome}` are replaced with the tick's values.
+    pub heartbeat_body: Option<String>,
+    /// Requested once when the check turns unhealthy.
+    pub fail_url: Option<String>,
+    #[serde(with = "parse_duration_opt", default)]
+    pub jitter: Option<std::time::Duration>,
+    /// Consecutive failures needed before the check is considered down.
+    #[serde(default = "default_threshold")]
+    pub failure_threshold: u32,
+    /// Consecutive successes needed before a down check is considered up again.
+    #[serde(default = "default_threshold")]
+    pub success_threshold: u32,
+}
+
+impl ConfigCheck {
+    /// Settings with default thresholds and no jitter or fail URL.
+    pub fn new(cron: impl Into<String>, heartbeat_url: impl Into<String>) -> Self {
+        Self {
+            name: None,
+            cron: cron.into(),
+            heartbeat_url: heartbeat_url.into(),
+            heartbeat_body: None,
+            fail_url: None,
+            jitter: None,
+            failure_threshold: default_threshold(),
+            success_threshold: default_threshold(),
+        }
+    }
+}
+
+fn default_threshold() -> u32 {
+    1
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConfigHttpPing {
+    pub target_url: String,
     pub status: Option<Vec<u16>>,
+    #[serde(flatten)]
+    pub check: ConfigCheck,
+}
+
+/// Freshness rule applied to the latest object under `prefix` in any object store.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConfigFreshness {
+    pub prefix: String,
+    #[serde(with = "parse_duration")]
+    pub grace: std::time::Duration,
+    #[serde(with = "parse_min_size", default)]
+    pub min_size: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ConfigS3Ping {
     pub region: String,
     pub bucket: String,
-    pub prefix: String,
-    pub cron: String,
+    #[serde(flatten)]
+    pub freshness: ConfigFreshness,
+    pub integrity: Option<ConfigIntegrity>,
+    #[serde(flatten)]
+    pub check: ConfigCheck,
+}
+
+/// Extra checks on the latest object, run after the freshness rule passed.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConfigIntegrity {
+    /// Compare the object against the sha256 stored in `<key>.sha256`.
+    #[serde(default)]
+    pub sha256_sidecar: bool,
+    /// User metadata that must be present with the given values.
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// Object tags that must be present with the given values.
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    /// Validate the first bytes of the object via a ranged GET.
+    pub header: Option<ConfigHeaderFormat>,
+}
 
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub enum ConfigHeaderFormat {
+    #[serde(rename = "gzip")]
+    Gzip,
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConfigGcsPing {
+    pub bucket: String,
+    /// Falls back to `GOOGLE_SERVICE_ACCOUNT` and friends when omitted.
+    pub service_account_path: Option<String>,
+    #[serde(flatten)]
+    pub freshness: ConfigFreshness,
+    #[serde(flatten)]
+    pub check: ConfigCheck,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConfigAzurePing {
+    pub account: String,
+    pub container: String,
+    /// Connect to Azurite instead of the real service.
+    #[serde(default)]
+    pub use_emulator: bool,
+    #[serde(flatten)]
+    pub freshness: ConfigFreshness,
+    #[serde(flatten)]
+    pub check: ConfigCheck,
+}
+
+/// Freshness rule applied to the newest file in `directory` on an SFTP server.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConfigSftpPing {
+    pub host: String,
+    #[serde(default = "default_sftp_port")]
+    pub port: u16,
+    pub user: String,
+    /// Private key used for public key authentication.
+    pub key_path: String,
+    /// OpenSSH `known_hosts` file to verify the server key against, `~/.ssh/known_hosts` when omitted.
+    pub known_hosts_path: Option<String>,
+    /// Accept any server key instead of checking `known_hosts_path`.
+    #[serde(default)]
+    pub insecure_skip_host_key: bool,
+    pub directory: String,
+    /// Only file names matching this pattern are considered.
+    #[serde(default = "default_sftp_glob")]
+    pub glob: String,
     #[serde(with = "parse_duration")]
     pub grace: std::time::Duration,
-    pub heartbeat_url: String,
     #[serde(with = "parse_min_size", default)]
     pub min_size: Option<u64>,
+    #[serde(flatten)]
+    pub check: ConfigCheck,
+}
+
+fn default_sftp_port() -> u16 {
+    22
+}
+
+fn default_sftp_glob() -> String {
+    "*".into()
+}
+
+/// Runs `query` and checks the first column of the first row.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConfigSqlPing {
+    /// Environment variable holding the DSN, e.g. `postgres://...`, `mysql://...` or `sqlite://...`.
+    pub dsn_env: String,
+    pub query: String,
+    /// The result is a timestamp (unix seconds or text) that must be newer than this.
+    #[serde(with = "parse_duration_opt", default)]
+    pub grace: Option<std::time::Duration>,
+    /// The result, rendered as text, must be equal to this.
+    pub expected: Option<String>,
+    #[serde(flatten)]
+    pub check: ConfigCheck,
+}
+
+/// Receives `GET /ping/<name>` from jobs and alerts when a ping is overdue.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConfigPush {
+    pub bind: String,
+    pub checks: Vec<ConfigPushPing>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConfigPushPing {
+    pub name: String,
+    /// Expected interval between two pings.
+    #[serde(with = "parse_duration")]
+    pub period: std::time::Duration,
+    #[serde(with = "parse_duration")]
+    pub grace: std::time::Duration,
+    #[serde(flatten)]
+    pub check: ConfigCheck,
 }
 
 mod parse_duration {
@@ -40,6 +394,22 @@ mod parse_duration {
     }
 }
 
+mod parse_duration_opt {
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<std::time::Duration>, D::Error> {
+        let duration_maybe: Option<String> = Deserialize::deserialize(deserializer)?;
+        match duration_maybe {
+            Some(duration) => Ok(Some(parse_duration::parse(&duration).map_err(|e| {
+                Error::custom(format!("failed to parse duration {duration}. Erro:{e:?}"))
+            })?)),
+            None => Ok(None),
+        }
+    }
+}
+
 mod parse_min_size {
 
     use serde::{de::Error, Deserialize, Deserializer};
@@ -56,3 +426,100 @@ mod parse_min_size {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(value: serde_json::Value) -> Config {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn merge_and_duplicate_names() {
+        let http = |name: &str| {
+            serde_json::json!({
+                "name": name,
+                "target_url": "https://example.com",
+                "cron": "0 * * * * *",
+                "heartbeat_url": "https://heartbeat.com",
+            })
+        };
+
+        let mut merged = config(serde_json::json!({ "max_concurrency": 4, "http": [http("a")] }));
+        merged
+            .merge(config(serde_json::json!({ "http": [http("b")] })))
+            .unwrap();
+        assert_eq!(merged.max_concurrency, Some(4));
+        assert_eq!(merged.http.as_ref().unwrap().len(), 2);
+        assert!(merged.check_duplicate_names().is_ok());
+
+        merged
+            .clone()
+            .merge(config(serde_json::json!({ "max_concurrency": 8 })))
+            .unwrap_err();
+
+        merged
+            .merge(config(serde_json::json!({ "http": [http("a")] })))
+            .unwrap();
+        assert!(merged.check_duplicate_names().is_err());
+    }
+
+    #[test]
+    fn duplicate_labels_across_files() {
+        let dir = std::env::temp_dir();
+        let first = dir.join(format!("heart-beater-first-{}.yaml", std::process::id()));
+        let second = dir.join(format!("heart-beater-second-{}.yaml", std::process::id()));
+        let http = "http:\n  - target_url: https://example.com\n    cron: \"0 * * * * *\"\n    heartbeat_url: https://heartbeat.com\n";
+        std::fs::write(&first, http).unwrap();
+        std::fs::write(&second, http).unwrap();
+
+        let duplicate = Config::load(&[first.clone(), second.clone()]);
+        std::fs::remove_file(&first).unwrap();
+        std::fs::remove_file(&second).unwrap();
+        let err = duplicate.err().unwrap().to_string();
+        assert!(err.contains("https://example.com"), "{err}");
+
+        // push checks run as `push/<name>`
+        let mut push = config(serde_json::json!({
+            "http": [{
+                "name": "push/backup",
+                "target_url": "https://example.com",
+                "cron": "0 * * * * *",
+                "heartbeat_url": "https://heartbeat.com",
+            }],
+        }));
+        push.merge(config(serde_json::json!({
+            "push": {
+                "bind": "127.0.0.1:0",
+                "checks": [{
+                    "name": "backup",
+                    "period": "1 day",
+                    "grace": "1 hour",
+                    "cron": "0 * * * * *",
+                    "heartbeat_url": "https://heartbeat.com",
+                }],
+            },
+        })))
+        .unwrap();
+        assert!(push.check_duplicate_names().is_err());
+    }
+
+    #[test]
+    fn load_reports_the_failing_file() {
+        let dir = std::env::temp_dir();
+        let empty = dir.join(format!("heart-beater-empty-{}.yaml", std::process::id()));
+        let multi = dir.join(format!("heart-beater-multi-{}.yaml", std::process::id()));
+        std::fs::write(&empty, "").unwrap();
+        std::fs::write(&multi, "max_concurrency: 1\n---\nmax_concurrency: 2\n").unwrap();
+
+        let loaded = Config::load(std::slice::from_ref(&empty));
+        let multi_err = Config::load(&[empty.clone(), multi.clone()]).err().unwrap();
+        std::fs::remove_file(&empty).unwrap();
+        std::fs::remove_file(&multi).unwrap();
+
+        // an empty file adds no checks
+        assert!(loaded.unwrap().http.is_none());
+        assert!(multi_err.to_string().contains("heart-beater-multi"));
+    }
+}
@@ -0,0 +1,83 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio_util::{
+    sync::CancellationToken,
+    task::{task_tracker::TrackedFuture, TaskTracker},
+};
+
+/// Tracks running ticks so that shutdown can wait for them to finish,
+/// including the heartbeat / fail pings they send.
+#[derive(Clone, Default)]
+pub struct Drain {
+    tracker: TaskTracker,
+    /// replaced by `resume`, a cancelled token cannot be reset
+    stopping: Arc<Mutex<CancellationToken>>,
+}
+
+impl Drain {
+    pub fn track<F: Future>(&self, future: F) -> TrackedFuture<F> {
+        self.tracker.track_future(future)
+    }
+
+    fn token(&self) -> CancellationToken {
+        self.stopping.lock().unwrap().clone()
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.token().is_cancelled()
+    }
+
+    /// Resolves once shutdown started; ticks that did not begin yet give up.
+    pub async fn stopping(&self) {
+        self.token().cancelled_owned().await
+    }
+
+    /// Starts the shutdown: ticks waiting to begin give up and `wait` no
+    /// longer expects new ones.
+    pub fn stop(&self) {
+        self.token().cancel();
+        self.tracker.close();
+    }
+
+    /// Undoes `stop` when the shutdown could not be completed.
+    pub fn resume(&self) {
+        *self.stopping.lock().unwrap() = CancellationToken::new();
+        self.tracker.reopen();
+    }
+
+    /// Returns the number of ticks still running when `timeout` elapsed,
+    /// after `stop`.
+    pub async fn wait(&self, timeout: Duration) -> usize {
+        match tokio::time::timeout(timeout, self.tracker.wait()).await {
+            Ok(()) => 0,
+            Err(_) => self.tracker.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stop_before_wait() {
+        let drain = Drain::default();
+        let tick = tokio::spawn(drain.track(async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }));
+
+        drain.stop();
+        // ticks see the shutdown before anything awaits the drain
+        assert!(drain.is_stopping());
+        drain.stopping().await;
+        assert_eq!(drain.wait(Duration::from_secs(5)).await, 0);
+        tick.await.unwrap();
+
+        drain.resume();
+        assert!(!drain.is_stopping());
+    }
+}
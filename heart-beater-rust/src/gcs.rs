@@ -0,0 +1,21 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use object_store::gcp::GoogleCloudStorageBuilder;
+
+use crate::{bucket::ObjectStoreCheck, config::ConfigGcsPing};
+
+pub fn new_check(config: &ConfigGcsPing) -> Result<ObjectStoreCheck> {
+    let mut builder = GoogleCloudStorageBuilder::from_env().with_bucket_name(&config.bucket);
+    if let Some(service_account_path) = config.service_account_path.as_ref() {
+        builder = builder.with_service_account_path(service_account_path);
+    }
+    let store = Arc::new(builder.build()?);
+
+    Ok(ObjectStoreCheck::new(
+        "gcs",
+        config.label(),
+        store,
+        config.freshness.clone(),
+    ))
+}
@@ -1,25 +1,37 @@
 use std::collections::HashSet;
 
 use anyhow::Result;
-use log::{info, warn};
-use tokio_cron_scheduler::{Job, JobScheduler};
+use log::debug;
 
-use crate::config::ConfigHttpPing;
+use crate::{
+    check::{Check, CheckOutcome, Observation},
+    config::ConfigHttpPing,
+};
 
-async fn tick(config: &ConfigHttpPing) -> Result<()> {
+async fn tick(
+    client: &reqwest::Client,
+    config: &ConfigHttpPing,
+    observation: &mut Observation,
+) -> Result<()> {
     let status_set: HashSet<u16> = config
         .status
         .clone()
         .unwrap_or(vec![200])
         .into_iter()
         .collect();
-    match reqwest::get(&config.target_url).await {
+    match client.get(&config.target_url).send().await {
         Ok(res) => {
-            info!("response => {res:?} / config={config:?}");
+            debug!("response => {res:?} / config={config:?}");
+            observation.status_code = Some(res.status().as_u16());
             if status_set.contains(&res.status().as_u16()) {
-                reqwest::get(&config.heartbeat_url).await?;
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!(
+                    "unexpected status {} from {}",
+                    res.status(),
+                    config.target_url
+                ))
             }
-            Ok(())
         }
         Err(err) => Err(anyhow::anyhow!(
             "Failed to get {}. {err:?}",
@@ -28,19 +40,30 @@ async fn tick(config: &ConfigHttpPing) -> Result<()> {
     }
 }
 
-pub async fn add_job(sched: &JobScheduler, config: ConfigHttpPing) -> Result<()> {
-    sched
-        .add(Job::new_async(config.cron.clone(), move |_uuid, _l| {
-            let config = config.clone();
-            Box::pin(async move {
-                match tick(&config).await {
-                    Ok(_) => {}
-                    Err(err) => {
-                        warn!("failed to access {config:?} {err:?}");
-                    }
-                }
-            })
-        })?)
-        .await?;
-    Ok(())
+pub struct HttpCheck {
+    client: reqwest::Client,
+    config: ConfigHttpPing,
+}
+
+impl HttpCheck {
+    /// `client` is the one the job pings with, see `add_job`.
+    pub fn new(config: ConfigHttpPing, client: reqwest::Client) -> Self {
+        Self { client, config }
+    }
+}
+
+impl Check for HttpCheck {
+    fn kind(&self) -> &'static str {
+        "http"
+    }
+
+    fn label(&self) -> String {
+        self.config.label()
+    }
+
+    async fn run(&self) -> CheckOutcome {
+        let mut observation = Observation::default();
+        let result = tick(&self.client, &self.config, &mut observation).await;
+        CheckOutcome::from(result).with_observation(observation)
+    }
 }
@@ -0,0 +1,227 @@
+use std::{collections::HashMap, io::Read};
+
+use anyhow::Result;
+use log::debug;
+use sha2::{Digest, Sha256};
+
+use crate::config::{ConfigHeaderFormat, ConfigIntegrity};
+
+pub const SHA256_SIDECAR_SUFFIX: &str = ".sha256";
+
+const TAR_BLOCK_SIZE: usize = 512;
+/// Compressed bytes fetched to inflate the first tar block of a `.tar.gz`.
+const TAR_GZ_RANGE_SIZE: usize = 64 * 1024;
+
+pub async fn check_integrity(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    integrity: &ConfigIntegrity,
+) -> Result<()> {
+    if !integrity.metadata.is_empty() {
+        let head = client.head_object().bucket(bucket).key(key).send().await?;
+        check_required(
+            "metadata",
+            key,
+            &integrity.metadata,
+            head.metadata().cloned().unwrap_or_default(),
+        )?;
+    }
+
+    if !integrity.tags.is_empty() {
+        let tagging = client
+            .get_object_tagging()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await?;
+        let tags = tagging
+            .tag_set()
+            .iter()
+            .map(|tag| (tag.key().to_string(), tag.value().to_string()))
+            .collect();
+        check_required("tag", key, &integrity.tags, tags)?;
+    }
+
+    if let Some(format) = integrity.header {
+        let size = match format {
+            ConfigHeaderFormat::Gzip | ConfigHeaderFormat::Tar => TAR_BLOCK_SIZE,
+            ConfigHeaderFormat::TarGz => TAR_GZ_RANGE_SIZE,
+        };
+        let head = client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .range(format!("bytes=0-{}", size - 1))
+            .send()
+            .await?
+            .body
+            .collect()
+            .await?
+            .into_bytes();
+        check_header(format, &head)
+            .map_err(|e| anyhow::anyhow!("key={key:?} invalid {format:?} header. {e}"))?;
+    }
+
+    if integrity.sha256_sidecar {
+        let sidecar_key = format!("{key}{SHA256_SIDECAR_SUFFIX}");
+        let sidecar = client
+            .get_object()
+            .bucket(bucket)
+            .key(&sidecar_key)
+            .send()
+            .await?
+            .body
+            .collect()
+            .await?
+            .into_bytes();
+        let expected = parse_sidecar(&sidecar)
+            .ok_or(anyhow::anyhow!("key={sidecar_key:?} no checksum found"))?;
+
+        let mut body = client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await?
+            .body;
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = body.next().await {
+            hasher.update(chunk?);
+        }
+        let actual = hex::encode(hasher.finalize());
+        debug!("sha256 key={key:?} expected={expected} actual={actual}");
+        if actual != expected {
+            return Err(anyhow::anyhow!(
+                "key={key:?} sha256 mismatch expected={expected} actual={actual}"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_required(
+    kind: &str,
+    key: &str,
+    required: &HashMap<String, String>,
+    actual: HashMap<String, String>,
+) -> Result<()> {
+    for (name, value) in required {
+        match actual.get(name) {
+            Some(v) if v == value => {}
+            v => {
+                return Err(anyhow::anyhow!(
+                    "key={key:?} {kind} {name}={v:?}, expected {value:?}"
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Accepts both a bare hex digest and `sha256sum` output (`<digest>  <filename>`).
+fn parse_sidecar(sidecar: &[u8]) -> Option<String> {
+    let digest = std::str::from_utf8(sidecar)
+        .ok()?
+        .split_whitespace()
+        .next()?;
+    if digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(digest.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+fn check_header(format: ConfigHeaderFormat, head: &[u8]) -> Result<()> {
+    match format {
+        ConfigHeaderFormat::Gzip => check_gzip_header(head),
+        ConfigHeaderFormat::Tar => check_tar_header(head),
+        ConfigHeaderFormat::TarGz => {
+            check_gzip_header(head)?;
+            let mut block = [0u8; TAR_BLOCK_SIZE];
+            flate2::read::GzDecoder::new(head).read_exact(&mut block)?;
+            check_tar_header(&block)
+        }
+    }
+}
+
+fn check_gzip_header(head: &[u8]) -> Result<()> {
+    // magic number followed by the deflate compression method
+    if head.len() < 10 || head[..3] != [0x1f, 0x8b, 0x08] {
+        return Err(anyhow::anyhow!("gzip magic number not found"));
+    }
+    Ok(())
+}
+
+fn check_tar_header(head: &[u8]) -> Result<()> {
+    if head.len() < TAR_BLOCK_SIZE {
+        return Err(anyhow::anyhow!("shorter than a tar block"));
+    }
+    let header = &head[..TAR_BLOCK_SIZE];
+
+    let stored =
+        std::str::from_utf8(&header[148..156])?.trim_matches(|c: char| c == '\0' || c == ' ');
+    let stored = u32::from_str_radix(stored, 8)?;
+    // the checksum field itself is summed as if it were filled with spaces
+    let actual: u32 = header
+        .iter()
+        .enumerate()
+        .map(|(i, b)| if (148..156).contains(&i) { b' ' } else { *b } as u32)
+        .sum();
+    if stored != actual {
+        return Err(anyhow::anyhow!(
+            "tar checksum mismatch stored={stored} actual={actual}"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn tar_block() -> Vec<u8> {
+        let mut block = vec![0u8; TAR_BLOCK_SIZE];
+        block[..8].copy_from_slice(b"hoge.txt");
+        block[156] = b'0';
+        block[257..263].copy_from_slice(b"ustar\0");
+        let sum: u32 = block
+            .iter()
+            .enumerate()
+            .map(|(i, b)| if (148..156).contains(&i) { b' ' } else { *b } as u32)
+            .sum();
+        block[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+        block[155] = b' ';
+        block
+    }
+
+    #[test]
+    fn headers() {
+        let tar = tar_block();
+        assert!(check_header(ConfigHeaderFormat::Tar, &tar).is_ok());
+
+        let mut broken = tar.clone();
+        broken[0] = b'x';
+        assert!(check_header(ConfigHeaderFormat::Tar, &broken).is_err());
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&tar).unwrap();
+        let tar_gz = encoder.finish().unwrap();
+        assert!(check_header(ConfigHeaderFormat::Gzip, &tar_gz).is_ok());
+        assert!(check_header(ConfigHeaderFormat::TarGz, &tar_gz).is_ok());
+        assert!(check_header(ConfigHeaderFormat::TarGz, &tar_gz[..20]).is_err());
+        assert!(check_header(ConfigHeaderFormat::Gzip, &tar).is_err());
+    }
+
+    #[test]
+    fn sidecar() {
+        let digest = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855";
+        assert_eq!(
+            parse_sidecar(format!("{digest}  backup.tar.gz\n").as_bytes()),
+            Some(digest.to_ascii_lowercase())
+        );
+        assert_eq!(parse_sidecar(b"not a digest"), None);
+    }
+}
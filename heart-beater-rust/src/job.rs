@@ -0,0 +1,187 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use anyhow::Result;
+use log::{debug, info, warn, Level};
+use tokio_cron_scheduler::{Job, JobScheduler};
+
+use crate::{
+    check::{Check, CheckOutcome, Observation},
+    config::ConfigCheck,
+    drain::Drain,
+    limiter::Limiter,
+    payload,
+    state::{CheckState, States, Transition},
+};
+
+/// Schedules `check` on `settings.cron`. Pings are sent according to the
+/// consecutive-result thresholds, and a structured event is emitted after
+/// every run.
+///
+/// Every run is tracked by `drain` so that shutdown can wait for it, and
+/// no new run starts once shutdown began. The state is registered in
+/// `states` for status output. `client` sends the pings and is shared with
+/// checks that make HTTP requests themselves, so ticks reuse connections.
+pub async fn add_job<C: Check>(
+    sched: &JobScheduler,
+    limiter: Limiter,
+    drain: Drain,
+    states: &States,
+    client: reqwest::Client,
+    settings: ConfigCheck,
+    check: C,
+) -> Result<()> {
+    let kind = check.kind();
+    let name = settings.name.clone().unwrap_or_else(|| check.label());
+    let state = Arc::new(Mutex::new(CheckState::new(
+        settings.failure_threshold,
+        settings.success_threshold,
+    )));
+    states.register(name.clone(), state.clone())?;
+    let check = Arc::new(check);
+    // Fail at startup instead of on the first healthy tick.
+    let _ = heartbeat_request(
+        &client,
+        kind,
+        &name,
+        &settings,
+        "ok",
+        0,
+        &Observation::default(),
+    )
+    .map_err(|e| anyhow::anyhow!("{name} has an invalid heartbeat template. {e}"))?;
+
+    sched
+        .add(Job::new_async(settings.cron.clone(), move |_uuid, _l| {
+            let limiter = limiter.clone();
+            let drain = drain.clone();
+            let name = name.clone();
+            let settings = settings.clone();
+            let state = state.clone();
+            let check = check.clone();
+            let client = client.clone();
+            if drain.is_stopping() {
+                return Box::pin(async {});
+            }
+            Box::pin(drain.clone().track(async move {
+                let _permit = tokio::select! {
+                    permit = limiter.acquire(settings.jitter) => permit,
+                    _ = drain.stopping() => {
+                        debug!("{name} skipped, shutting down");
+                        return;
+                    }
+                };
+                let started = Instant::now();
+                let outcome = check.run().await;
+                let duration_ms = started.elapsed().as_millis() as u64;
+                let notified = notify(
+                    &client,
+                    kind,
+                    &name,
+                    &settings,
+                    &state,
+                    duration_ms,
+                    outcome,
+                );
+                if let Err(err) = notified.await {
+                    warn!("{name} failed to notify {err:?}");
+                }
+            }))
+        })?)
+        .await?;
+    Ok(())
+}
+
+async fn notify(
+    client: &reqwest::Client,
+    kind: &str,
+    name: &str,
+    settings: &ConfigCheck,
+    state: &Mutex<CheckState>,
+    duration_ms: u64,
+    outcome: CheckOutcome,
+) -> Result<()> {
+    let CheckOutcome {
+        observation,
+        result,
+    } = outcome;
+    let outcome = if result.is_ok() { "ok" } else { "fail" };
+    let (transition, healthy) = {
+        let mut state = state.lock().unwrap();
+        let transition = state.record(result.is_ok());
+        let error = result.as_ref().err().map(|err| format!("{err:#}"));
+        log::log!(
+            target: "tick",
+            if result.is_ok() { Level::Info } else { Level::Warn },
+            check = name,
+            kind = kind,
+            duration_ms = duration_ms,
+            outcome = outcome,
+            healthy = state.healthy(),
+            consecutive_successes = state.consecutive_successes(),
+            consecutive_failures = state.consecutive_failures(),
+            status_code = observation.status_code,
+            object_key = observation.object_key.as_deref(),
+            object_size = observation.object_size,
+            value = observation.value.as_deref(),
+            age_secs = observation.age_secs,
+            error = error.as_deref();
+            "tick finished"
+        );
+        (transition, state.healthy())
+    };
+
+    match transition {
+        Transition::Down => {
+            warn!("{name} is down");
+            if let Some(fail_url) = settings.fail_url.as_ref() {
+                client.get(fail_url).send().await?;
+            }
+        }
+        Transition::Up => info!("{name} recovered"),
+        Transition::None => {}
+    }
+
+    if healthy {
+        heartbeat_request(
+            client,
+            kind,
+            name,
+            settings,
+            outcome,
+            duration_ms,
+            &observation,
+        )?
+        .send()
+        .await?;
+    }
+    Ok(())
+}
+
+fn heartbeat_request(
+    client: &reqwest::Client,
+    kind: &str,
+    name: &str,
+    settings: &ConfigCheck,
+    outcome: &str,
+    duration_ms: u64,
+    observation: &Observation,
+) -> Result<reqwest::RequestBuilder> {
+    let values = payload::Values {
+        check: name,
+        kind,
+        outcome,
+        latency_ms: duration_ms,
+        observation,
+    };
+    let url = payload::render(&settings.heartbeat_url, &values, payload::escape_url)?;
+    Ok(match settings.heartbeat_body.as_ref() {
+        Some(body) => client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(payload::render(body, &values, payload::escape_none)?),
+        None => client.get(url),
+    })
+}
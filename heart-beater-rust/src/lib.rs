@@ -1,4 +1,28 @@
+//! Scheduled freshness / availability checks that ping a heartbeat URL.
+//!
+//! Besides the `heart-beater-rust` binary, checks can be embedded in other
+//! services: implement [`Check`] and register it on a [`Scheduler`].
+
+mod azure;
+mod bucket;
+mod check;
 pub mod cli;
-mod config;
+pub mod config;
+mod drain;
+mod gcs;
 mod http;
+mod integrity;
+mod job;
+mod limiter;
+mod logging;
+mod payload;
+mod push;
 mod s3;
+mod scheduler;
+mod sftp;
+mod sql;
+mod state;
+
+pub use check::{Check, CheckOutcome, Observation};
+pub use scheduler::{Scheduler, SchedulerBuilder};
+pub use state::CheckStatus;
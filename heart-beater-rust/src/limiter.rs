@@ -0,0 +1,41 @@
+use std::{sync::Arc, time::Duration};
+
+use log::debug;
+use rand::Rng;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Shared across every job so that ticks sharing the same cron expression
+/// do not all hit their targets at the same instant.
+#[derive(Clone, Default)]
+pub struct Limiter {
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+impl Limiter {
+    pub fn new(max_concurrency: Option<usize>) -> Self {
+        Self {
+            semaphore: max_concurrency.map(|n| Arc::new(Semaphore::new(n.max(1)))),
+        }
+    }
+
+    /// Sleeps for a random delay in `0..=jitter`, then waits for a free slot.
+    /// The returned permit must be held for the whole tick.
+    pub async fn acquire(&self, jitter: Option<Duration>) -> Option<OwnedSemaphorePermit> {
+        if let Some(jitter) = jitter {
+            let delay = rand::thread_rng().gen_range(Duration::ZERO..=jitter);
+            debug!("jitter => {delay:?}");
+            tokio::time::sleep(delay).await;
+        }
+
+        match self.semaphore.as_ref() {
+            Some(semaphore) => Some(
+                semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore is never closed"),
+            ),
+            None => None,
+        }
+    }
+}
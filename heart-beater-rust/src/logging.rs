@@ -0,0 +1,49 @@
+use std::io::Write;
+
+use clap::ValueEnum;
+use log::kv::{self, VisitSource};
+use serde_json::{Map, Value};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum LogFormat {
+    /// env_logger's human readable format, with key-values appended
+    Text,
+    /// one JSON object per line, key-values as top level fields
+    Json,
+}
+
+pub fn init(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if let LogFormat::Json = format {
+        builder.format(|buf, record| {
+            let mut event = Map::new();
+            event.insert(
+                "ts".into(),
+                chrono::Utc::now()
+                    .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+                    .into(),
+            );
+            event.insert("level".into(), record.level().as_str().into());
+            event.insert("target".into(), record.target().into());
+            event.insert("message".into(), record.args().to_string().into());
+            record
+                .key_values()
+                .visit(&mut JsonVisitor(&mut event))
+                .map_err(std::io::Error::other)?;
+            writeln!(buf, "{}", Value::Object(event))
+        });
+    }
+    builder.init();
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonVisitor<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = serde_json::to_value(&value).map_err(kv::Error::boxed)?;
+        if !value.is_null() {
+            self.0.insert(key.to_string(), value);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::Result;
+
+use crate::check::Observation;
+
+/// Values available as `{placeholder}` in `heartbeat_url` and `heartbeat_body`.
+/// Values the check did not observe render as an empty string.
+pub struct Values<'a> {
+    pub check: &'a str,
+    pub kind: &'a str,
+    pub outcome: &'a str,
+    pub latency_ms: u64,
+    pub observation: &'a Observation,
+}
+
+impl Values<'_> {
+    fn get(&self, key: &str) -> Option<String> {
+        fn opt<T: ToString>(value: Option<T>) -> String {
+            value.map(|v| v.to_string()).unwrap_or_default()
+        }
+        let observation = self.observation;
+        Some(match key {
+            "check" => self.check.to_string(),
+            "kind" => self.kind.to_string(),
+            "outcome" => self.outcome.to_string(),
+            "latency_ms" => self.latency_ms.to_string(),
+            "status_code" => opt(observation.status_code),
+            "object_key" => opt(observation.object_key.as_ref()),
+            "object_size" => opt(observation.object_size),
+            "age_secs" => opt(observation.age_secs),
+            "value" => opt(observation.value.as_ref()),
+            _ => return None,
+        })
+    }
+}
+
+/// Replaces every `{placeholder}` in `template`, passing values through `escape`.
+/// `{{` and `}}` stand for literal braces, e.g. in JSON bodies.
+pub fn render(template: &str, values: &Values, escape: fn(&str) -> String) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let brace = &rest[..1];
+        let doubled = rest[1..].starts_with(brace);
+        // a lone `}` is kept as it is
+        if doubled || brace == "}" {
+            rendered.push_str(brace);
+            rest = &rest[if doubled { 2 } else { 1 }..];
+            continue;
+        }
+        let end = rest
+            .find('}')
+            .ok_or(anyhow::anyhow!("unclosed placeholder in {template:?}"))?;
+        let key = &rest[1..end];
+        let value = values.get(key).ok_or(anyhow::anyhow!(
+            "unknown placeholder {{{key}}} in {template:?}"
+        ))?;
+        rendered.push_str(&escape(&value));
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+pub fn escape_url(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+pub fn escape_none(value: &str) -> String {
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders() {
+        let observation = Observation {
+            status_code: Some(200),
+            object_key: Some("backup/db 1.gz".into()),
+            age_secs: Some(42),
+            ..Default::default()
+        };
+        let values = Values {
+            check: "db",
+            kind: "s3",
+            outcome: "ok",
+            latency_ms: 12,
+            observation: &observation,
+        };
+
+        assert_eq!(
+            render(
+                "https://kuma/api/push/x?msg={object_key}&ping={latency_ms}",
+                &values,
+                escape_url
+            )
+            .unwrap(),
+            "https://kuma/api/push/x?msg=backup%2Fdb+1.gz&ping=12"
+        );
+        assert_eq!(
+            render(
+                "{check} {status_code} age={age_secs}s size={object_size}",
+                &values,
+                escape_none
+            )
+            .unwrap(),
+            "db 200 age=42s size="
+        );
+        assert_eq!(
+            render(
+                r#"{{"msg":"{object_key}","ms":{latency_ms}}}"#,
+                &values,
+                escape_none
+            )
+            .unwrap(),
+            r#"{"msg":"backup/db 1.gz","ms":12}"#
+        );
+        assert!(render("{nope}", &values, escape_none).is_err());
+        assert!(render("{check", &values, escape_none).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info};
+use tokio::task::JoinHandle;
+
+use crate::{
+    check::{Check, CheckOutcome, Observation},
+    config::{ConfigCheck, ConfigPush, ConfigPushPing},
+};
+
+/// Time of the last ping received for every configured name.
+///
+/// Names start with the daemon start time so that a fresh start does not
+/// alert before a job had the chance to ping once. On reload names that are
+/// still configured keep their last ping, so an overdue job stays overdue.
+#[derive(Clone)]
+pub struct Registry {
+    last_pings: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+}
+
+impl Registry {
+    fn new<'a>(names: impl Iterator<Item = &'a str>, previous: Option<&Registry>) -> Self {
+        let now = Utc::now();
+        let previous = previous
+            .map(|registry| registry.last_pings.lock().unwrap().clone())
+            .unwrap_or_default();
+        Self {
+            last_pings: Arc::new(Mutex::new(
+                names
+                    .map(|name| {
+                        let at = previous.get(name).copied().unwrap_or(now);
+                        (name.to_string(), at)
+                    })
+                    .collect(),
+            )),
+        }
+    }
+
+    fn ping(&self, name: &str) -> bool {
+        match self.last_pings.lock().unwrap().get_mut(name) {
+            Some(at) => {
+                *at = Utc::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn last_ping(&self, name: &str) -> Option<DateTime<Utc>> {
+        self.last_pings.lock().unwrap().get(name).copied()
+    }
+}
+
+async fn ping(State(registry): State<Registry>, Path(name): Path<String>) -> StatusCode {
+    if registry.ping(&name) {
+        debug!("ping received => {name}");
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+fn tick(registry: &Registry, config: &ConfigPushPing, observation: &mut Observation) -> Result<()> {
+    let last_ping = registry
+        .last_ping(&config.name)
+        .ok_or(anyhow::anyhow!("{} is not registered", config.name))?;
+    observation.set_age(last_ping);
+    let deadline = last_ping + config.period + config.grace;
+    debug!("last ping => {last_ping} / config={config:?}");
+    if Utc::now() < deadline {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "{} has not pinged since {last_ping}",
+            config.name
+        ))
+    }
+}
+
+pub struct PushCheck {
+    registry: Registry,
+    config: ConfigPushPing,
+}
+
+impl Check for PushCheck {
+    fn kind(&self) -> &'static str {
+        "push"
+    }
+
+    fn label(&self) -> String {
+        self.config.label()
+    }
+
+    async fn run(&self) -> CheckOutcome {
+        let mut observation = Observation::default();
+        let result = tick(&self.registry, &self.config, &mut observation);
+        CheckOutcome::from(result).with_observation(observation)
+    }
+}
+
+/// Pings received by a running listener and the checks reading them.
+pub struct Started {
+    /// aborting it closes the listener
+    pub server: JoinHandle<()>,
+    pub registry: Registry,
+    pub checks: Vec<(ConfigCheck, PushCheck)>,
+}
+
+/// Starts the listener in the background and returns one overdue check per
+/// name. Last pings are taken over from `previous`, the registry of the
+/// listener this one replaces.
+pub async fn start(config: ConfigPush, previous: Option<&Registry>) -> Result<Started> {
+    let registry = Registry::new(config.checks.iter().map(|c| c.name.as_str()), previous);
+
+    let app = Router::new()
+        .route("/ping/{name}", get(ping))
+        .with_state(registry.clone());
+    let listener = tokio::net::TcpListener::bind(&config.bind).await?;
+    info!("push receiver listening on {}", config.bind);
+    let server = tokio::spawn(async move {
+        if let Err(err) = axum::serve(listener, app).await {
+            error!("push receiver stopped {err:?}");
+        }
+    });
+
+    let checks = config
+        .checks
+        .into_iter()
+        .map(|config| {
+            let settings = config.check.clone();
+            let check = PushCheck {
+                registry: registry.clone(),
+                config,
+            };
+            (settings, check)
+        })
+        .collect();
+    Ok(Started {
+        server,
+        registry,
+        checks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload_keeps_last_pings() {
+        let config: ConfigPushPing = serde_json::from_value(serde_json::json!({
+            "name": "nightly-backup",
+            "period": "1 day",
+            "grace": "1 hour",
+            "cron": "0 * * * * *",
+            "heartbeat_url": "http://localhost",
+        }))
+        .unwrap();
+        let before = Registry::new(["nightly-backup", "removed"].into_iter(), None);
+        let overdue = Utc::now() - chrono::Duration::days(2);
+        for at in before.last_pings.lock().unwrap().values_mut() {
+            *at = overdue;
+        }
+        assert!(tick(&before, &config, &mut Observation::default()).is_err());
+
+        let reloaded = Registry::new(["nightly-backup", "added"].into_iter(), Some(&before));
+        assert_eq!(reloaded.last_ping("nightly-backup"), Some(overdue));
+        assert!(reloaded.last_ping("removed").is_none());
+        assert!(reloaded.last_ping("added").unwrap() > overdue);
+        assert!(tick(&reloaded, &config, &mut Observation::default()).is_err());
+    }
+}
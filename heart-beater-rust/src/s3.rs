@@ -2,17 +2,24 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use aws_sdk_s3::types::Object;
-use log::{debug, info, warn};
-use tokio_cron_scheduler::{Job, JobScheduler};
+use log::debug;
 
-use crate::config::ConfigS3Ping;
+use crate::{
+    bucket::{check_freshness, LatestObject},
+    check::{Check, CheckOutcome, Observation},
+    config::ConfigS3Ping,
+    integrity::{check_integrity, SHA256_SIDECAR_SUFFIX},
+};
 
+/// S3 stays on the AWS SDK instead of `bucket::ObjectStoreCheck` since the
+/// integrity checks need object metadata and tags.
 async fn get_latest_object(
     client: &aws_sdk_s3::Client,
     bucket: &str,
     prefix: &str,
-) -> Option<Object> {
-    let mut objects = client
+    skip_suffix: Option<&str>,
+) -> Result<Option<Object>> {
+    let mut pages = client
         .list_objects_v2()
         .bucket(bucket)
         .prefix(prefix)
@@ -20,76 +27,99 @@ async fn get_latest_object(
         .send();
 
     let mut latest_object: Option<Object> = None;
-    while let Some(Ok(object_lis)) = objects.next().await {
-        for object in object_lis.contents() {
+    while let Some(page) = pages.next().await {
+        let page =
+            page.map_err(|e| anyhow::anyhow!("failed to list s3://{bucket}/{prefix}. {e}"))?;
+        for object in page.contents() {
             debug!("get object => {object:?}");
-            if let Some(last_modified) = object.last_modified() {
-                if let Some(lo) = latest_object.as_ref() {
-                    if let Some(dt) = lo.last_modified() {
-                        if last_modified > dt {
-                            latest_object = Some(object.clone());
-                        }
-                    } else {
-                        latest_object = Some(object.clone());
-                    }
-                } else {
-                    latest_object = Some(object.clone());
+            if let (Some(suffix), Some(key)) = (skip_suffix, object.key()) {
+                if key.ends_with(suffix) {
+                    continue;
                 }
             }
+            let Some(last_modified) = object.last_modified() else {
+                continue;
+            };
+            if latest_object
+                .as_ref()
+                .and_then(|lo| lo.last_modified())
+                .is_none_or(|dt| last_modified > dt)
+            {
+                latest_object = Some(object.clone());
+            }
         }
     }
 
-    latest_object
+    Ok(latest_object)
 }
 
-async fn tick(client: &aws_sdk_s3::Client, config: &ConfigS3Ping) -> Result<()> {
-    match get_latest_object(client, &config.bucket, &config.prefix).await {
+async fn tick(
+    client: &aws_sdk_s3::Client,
+    config: &ConfigS3Ping,
+    observation: &mut Observation,
+) -> Result<()> {
+    let skip_suffix = config
+        .integrity
+        .as_ref()
+        .filter(|integrity| integrity.sha256_sidecar)
+        .map(|_| SHA256_SIDECAR_SUFFIX);
+    match get_latest_object(
+        client,
+        &config.bucket,
+        &config.freshness.prefix,
+        skip_suffix,
+    )
+    .await?
+    {
         Some(object) => {
-            info!("latest object => {object:?} / config={config:?}");
-            if let Some(min_size) = config.min_size.as_ref() {
-                let size = object
-                    .size()
-                    .ok_or(anyhow::anyhow!("key={:?} no size found", object.key()))?;
-                if size < (*min_size as i64) {
-                    return Ok(());
-                }
-            }
-            let at = object
-                .last_modified()
-                .ok_or(anyhow::anyhow!(
-                    "key={:?} no last_modified found",
-                    object.key()
-                ))?
-                .to_millis()?;
-
-            if at + config.grace.as_millis() as i64 > chrono::Utc::now().timestamp_millis() {
-                reqwest::get(&config.heartbeat_url).await?;
+            debug!("latest object => {object:?} / config={config:?}");
+            let latest = LatestObject {
+                key: object.key().unwrap_or_default().to_string(),
+                size: object.size().map(|size| size as u64),
+                last_modified: object
+                    .last_modified()
+                    .map(|dt| dt.to_millis())
+                    .transpose()?
+                    .and_then(chrono::DateTime::from_timestamp_millis),
+            };
+            latest.observe(observation);
+            check_freshness(&latest, &config.freshness)?;
+            if let Some(integrity) = config.integrity.as_ref() {
+                check_integrity(client, &config.bucket, &latest.key, integrity).await?;
             }
-
             Ok(())
         }
-        None => Err(anyhow::anyhow!("not data found. or failed to access")),
+        None => Err(anyhow::anyhow!(
+            "no object found under s3://{}/{}",
+            config.bucket,
+            config.freshness.prefix
+        )),
     }
 }
 
-pub async fn add_job(
-    sched: &JobScheduler,
+pub struct S3Check {
     client: Arc<aws_sdk_s3::Client>,
     config: ConfigS3Ping,
-) -> Result<()> {
-    sched
-        .add(Job::new_async(config.cron.clone(), move |_uuid, _l| {
-            let client = client.clone();
-            let config = config.clone();
-            Box::pin(async move {
-                match tick(&client, &config).await {
-                    Ok(_) => {}
-                    Err(err) => {
-                        warn!("failed to access {config:?} {err:?}");
-                    }
-                }
-            })
-        })?)
-        .await?;
-    Ok(())
+}
+
+impl S3Check {
+    pub fn new(client: Arc<aws_sdk_s3::Client>, config: ConfigS3Ping) -> Self {
+        Self { client, config }
+    }
+}
+
+impl Check for S3Check {
+    fn kind(&self) -> &'static str {
+        "s3"
+    }
+
+    fn label(&self) -> String {
+        self.config.label()
+    }
+
+    async fn run(&self) -> CheckOutcome {
+        let mut observation = Observation::default();
+        let result = tick(&self.client, &self.config, &mut observation).await;
+        CheckOutcome::from(result).with_observation(observation)
+    }
 }
@@ -0,0 +1,246 @@
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use aws_config::Region;
+use log::{debug, warn};
+use tokio::task::JoinHandle;
+use tokio_cron_scheduler::JobScheduler;
+
+use crate::{
+    check::Check,
+    config::{Config, ConfigCheck},
+    drain::Drain,
+    http::HttpCheck,
+    limiter::Limiter,
+    push,
+    s3::S3Check,
+    sftp::SftpCheck,
+    sql::SqlCheck,
+    state::{CheckStatus, States},
+};
+
+type Registration =
+    Box<dyn FnOnce(Jobs) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send>;
+
+/// What every job of one scheduler shares, see [`super::job::add_job`].
+#[derive(Clone)]
+struct Jobs {
+    sched: JobScheduler,
+    limiter: Limiter,
+    drain: Drain,
+    states: States,
+}
+
+impl Jobs {
+    async fn add<C: Check>(&self, settings: ConfigCheck, check: C) -> Result<()> {
+        self.add_with_client(reqwest::Client::new(), settings, check)
+            .await
+    }
+
+    /// For checks that send their own requests with `client`.
+    async fn add_with_client<C: Check>(
+        &self,
+        client: reqwest::Client,
+        settings: ConfigCheck,
+        check: C,
+    ) -> Result<()> {
+        super::job::add_job(
+            &self.sched,
+            self.limiter.clone(),
+            self.drain.clone(),
+            &self.states,
+            client,
+            settings,
+            check,
+        )
+        .await
+    }
+}
+
+/// Collects built-in checks from a [`Config`] and custom [`Check`]s.
+#[derive(Default)]
+pub struct SchedulerBuilder {
+    max_concurrency: Option<usize>,
+    config: Config,
+    checks: Vec<Registration>,
+    push_pings: Option<push::Registry>,
+}
+
+impl SchedulerBuilder {
+    /// Overrides `max_concurrency` of the config.
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = Some(max_concurrency);
+        self
+    }
+
+    /// Built-in checks to schedule. Replaces any previously set config.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Last pings of the scheduler being replaced, see [`push::Registry`].
+    pub(crate) fn push_pings(mut self, registry: Option<push::Registry>) -> Self {
+        self.push_pings = registry;
+        self
+    }
+
+    pub fn check<C: Check>(mut self, settings: ConfigCheck, check: C) -> Self {
+        self.checks.push(Box::new(move |jobs| {
+            Box::pin(async move { jobs.add(settings, check).await })
+        }));
+        self
+    }
+
+    pub async fn build(self) -> Result<Scheduler> {
+        let config = self.config;
+        let jobs = Jobs {
+            sched: JobScheduler::new().await?,
+            limiter: Limiter::new(self.max_concurrency.or(config.max_concurrency)),
+            drain: Drain::default(),
+            states: States::default(),
+        };
+        let mut push_pings = None;
+        let mut tasks = Vec::new();
+
+        if let Some(http_list) = config.http {
+            for http_config in http_list {
+                debug!("http => {http_config:?}");
+                let settings = http_config.check.clone();
+                let client = reqwest::Client::new();
+                let check = HttpCheck::new(http_config, client.clone());
+                jobs.add_with_client(client, settings, check).await?;
+            }
+        }
+
+        if let Some(s3_list) = config.s3 {
+            if !s3_list.is_empty() {
+                let mut client_map: HashMap<String, Arc<aws_sdk_s3::Client>> = HashMap::new();
+
+                for s3_config in s3_list {
+                    let client = if let Some(client) = client_map.get(&s3_config.region) {
+                        client.clone()
+                    } else {
+                        let region = Region::new(s3_config.region.clone());
+                        let sdk_config = aws_config::from_env().region(region).load().await;
+                        let client = Arc::new(aws_sdk_s3::Client::new(&sdk_config));
+                        client_map.insert(s3_config.region.clone(), client.clone());
+                        client
+                    };
+
+                    debug!("s3 => {s3_config:?}");
+                    let settings = s3_config.check.clone();
+                    let check = S3Check::new(client.clone(), s3_config);
+                    jobs.add(settings, check).await?;
+                }
+            }
+        }
+
+        if let Some(gcs_list) = config.gcs {
+            for gcs_config in gcs_list {
+                debug!("gcs => {gcs_config:?}");
+                let check = super::gcs::new_check(&gcs_config)?;
+                jobs.add(gcs_config.check, check).await?;
+            }
+        }
+
+        if let Some(azure_list) = config.azure {
+            for azure_config in azure_list {
+                debug!("azure => {azure_config:?}");
+                let check = super::azure::new_check(&azure_config)?;
+                jobs.add(azure_config.check, check).await?;
+            }
+        }
+
+        if let Some(sql_list) = config.sql {
+            for sql_config in sql_list {
+                debug!("sql => {sql_config:?}");
+                let settings = sql_config.check.clone();
+                let check = SqlCheck::new(sql_config)?;
+                jobs.add(settings, check).await?;
+            }
+        }
+
+        if let Some(sftp_list) = config.sftp {
+            for sftp_config in sftp_list {
+                debug!("sftp => {sftp_config:?}");
+                let settings = sftp_config.check.clone();
+                let check = SftpCheck::new(sftp_config)?;
+                jobs.add(settings, check).await?;
+            }
+        }
+
+        if let Some(push_config) = config.push {
+            let started = super::push::start(push_config, self.push_pings.as_ref()).await?;
+            tasks.push(started.server);
+            push_pings = Some(started.registry);
+            for (settings, check) in started.checks {
+                jobs.add(settings, check).await?;
+            }
+        }
+
+        for register in self.checks {
+            register(jobs.clone()).await?;
+        }
+
+        Ok(Scheduler {
+            sched: jobs.sched,
+            drain: jobs.drain,
+            states: jobs.states,
+            push_pings,
+            tasks,
+        })
+    }
+}
+
+/// Runs registered checks on their cron schedule.
+pub struct Scheduler {
+    sched: JobScheduler,
+    drain: Drain,
+    states: States,
+    push_pings: Option<push::Registry>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    pub fn builder() -> SchedulerBuilder {
+        SchedulerBuilder::default()
+    }
+
+    /// Signals are left to the caller; see [`Scheduler::shutdown`].
+    pub async fn start(&self) -> Result<()> {
+        self.sched.start().await?;
+        Ok(())
+    }
+
+    /// Health and consecutive counters of every check, in registration order.
+    pub fn status(&self) -> Vec<CheckStatus> {
+        self.states.status()
+    }
+
+    pub(crate) fn push_pings(&self) -> Option<push::Registry> {
+        self.push_pings.clone()
+    }
+
+    /// Stops scheduling new runs, waits up to `timeout` for running ticks and
+    /// their pings to finish, then closes background listeners. When stopping
+    /// the schedule fails the checks keep running.
+    pub async fn shutdown(&mut self, timeout: Duration) -> Result<()> {
+        self.drain.stop();
+        if let Err(err) = self.sched.shutdown().await {
+            self.drain.resume();
+            return Err(err.into());
+        }
+        let remaining = self.drain.wait(timeout).await;
+        if remaining > 0 {
+            warn!("shutdown deadline exceeded, {remaining} tick(s) dropped");
+        } else {
+            debug!("all ticks finished");
+        }
+        for task in self.tasks.drain(..) {
+            task.abort();
+            let _ = task.await;
+        }
+        Ok(())
+    }
+}
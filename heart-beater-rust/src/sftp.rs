@@ -0,0 +1,295 @@
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::DateTime;
+use glob::Pattern;
+use log::{debug, warn};
+use ssh2::{CheckResult, FileStat, KnownHostFileKind, Session};
+
+use crate::{
+    bucket::{check_freshness, LatestObject},
+    check::{Check, CheckOutcome, Observation},
+    config::{ConfigFreshness, ConfigSftpPing},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `known_hosts` is `None` when host key checking is disabled.
+fn connect(config: &ConfigSftpPing, known_hosts: Option<&Path>) -> Result<Session> {
+    let addr = (config.host.as_str(), config.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or(anyhow::anyhow!("failed to resolve {}", config.host))?;
+    let tcp = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+
+    let mut session = Session::new()?;
+    session.set_timeout(TIMEOUT.as_millis() as u32);
+    session.set_tcp_stream(tcp);
+    session.handshake()?;
+
+    if let Some(known_hosts_path) = known_hosts {
+        let mut known_hosts = session.known_hosts()?;
+        known_hosts
+            .read_file(known_hosts_path, KnownHostFileKind::OpenSSH)
+            .map_err(|e| anyhow::anyhow!("failed to read {}. {e}", known_hosts_path.display()))?;
+        let (key, _) = session
+            .host_key()
+            .ok_or(anyhow::anyhow!("{} sent no host key", config.host))?;
+        match known_hosts.check_port(&config.host, config.port, key) {
+            CheckResult::Match => {}
+            result => {
+                return Err(anyhow::anyhow!(
+                    "host key of {} is not trusted. {result:?}",
+                    config.host
+                ))
+            }
+        }
+    }
+
+    session.userauth_pubkey_file(&config.user, None, Path::new(&config.key_path), None)?;
+    Ok(session)
+}
+
+fn latest_file(entries: Vec<(PathBuf, FileStat)>, pattern: &Pattern) -> Option<LatestObject> {
+    entries
+        .into_iter()
+        .filter(|(path, stat)| {
+            stat.is_file()
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| pattern.matches(name))
+        })
+        .max_by_key(|(_, stat)| stat.mtime)
+        .map(|(path, stat)| LatestObject {
+            key: path.to_string_lossy().into_owned(),
+            size: stat.size,
+            last_modified: stat
+                .mtime
+                .and_then(|mtime| DateTime::from_timestamp(mtime as i64, 0)),
+        })
+}
+
+/// Blocking; run it on the blocking pool.
+fn tick(
+    config: &ConfigSftpPing,
+    known_hosts: Option<&Path>,
+    pattern: &Pattern,
+    freshness: &ConfigFreshness,
+    observation: &mut Observation,
+) -> Result<()> {
+    let session = connect(config, known_hosts)?;
+    let entries = session.sftp()?.readdir(Path::new(&config.directory))?;
+    let latest_file = latest_file(entries, pattern).ok_or(anyhow::anyhow!(
+        "no file matching {} found in {}",
+        config.glob,
+        config.directory
+    ))?;
+    debug!("latest file => {latest_file:?} / config={config:?}");
+    latest_file.observe(observation);
+
+    check_freshness(&latest_file, freshness)
+}
+
+pub struct SftpCheck {
+    config: ConfigSftpPing,
+    known_hosts: Option<PathBuf>,
+    pattern: Pattern,
+    freshness: ConfigFreshness,
+}
+
+impl SftpCheck {
+    pub fn new(config: ConfigSftpPing) -> Result<Self> {
+        let pattern = Pattern::new(&config.glob)
+            .map_err(|e| anyhow::anyhow!("invalid glob {}. {e}", config.glob))?;
+        let known_hosts = if config.insecure_skip_host_key {
+            warn!(
+                "host key of {} is not verified, insecure_skip_host_key is set",
+                config.host
+            );
+            None
+        } else {
+            Some(match config.known_hosts_path.as_ref() {
+                Some(path) => PathBuf::from(path),
+                None => std::env::var_os("HOME")
+                    .map(|home| Path::new(&home).join(".ssh/known_hosts"))
+                    .ok_or(anyhow::anyhow!(
+                        "known_hosts_path is required for {}, HOME is not set",
+                        config.host
+                    ))?,
+            })
+        };
+        let freshness = ConfigFreshness {
+            prefix: config.directory.clone(),
+            grace: config.grace,
+            min_size: config.min_size,
+        };
+        Ok(Self {
+            config,
+            known_hosts,
+            pattern,
+            freshness,
+        })
+    }
+}
+
+impl Check for SftpCheck {
+    fn kind(&self) -> &'static str {
+        "sftp"
+    }
+
+    fn label(&self) -> String {
+        self.config.label()
+    }
+
+    async fn run(&self) -> CheckOutcome {
+        let config = self.config.clone();
+        let known_hosts = self.known_hosts.clone();
+        let pattern = self.pattern.clone();
+        let freshness = self.freshness.clone();
+        let joined = tokio::task::spawn_blocking(move || {
+            let mut observation = Observation::default();
+            let result = tick(
+                &config,
+                known_hosts.as_deref(),
+                &pattern,
+                &freshness,
+                &mut observation,
+            );
+            (result, observation)
+        })
+        .await;
+        match joined {
+            Ok((result, observation)) => CheckOutcome::from(result).with_observation(observation),
+            Err(err) => CheckOutcome::fail(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn stat(size: u64, mtime: u64, perm: u32) -> FileStat {
+        FileStat {
+            size: Some(size),
+            uid: None,
+            gid: None,
+            perm: Some(perm),
+            atime: None,
+            mtime: Some(mtime),
+        }
+    }
+
+    #[test]
+    fn latest_matching_file() {
+        let entries = vec![
+            (PathBuf::from("backup/db-1.sql.gz"), stat(10, 100, 0o100644)),
+            (PathBuf::from("backup/db-2.sql.gz"), stat(20, 200, 0o100644)),
+            (PathBuf::from("backup/db-3.log"), stat(30, 300, 0o100644)),
+            (PathBuf::from("backup/db-4.sql.gz"), stat(0, 400, 0o040755)),
+        ];
+        let latest = latest_file(entries, &Pattern::new("db-*.sql.gz").unwrap()).unwrap();
+        assert_eq!(latest.key, "backup/db-2.sql.gz");
+        assert_eq!(latest.size, Some(20));
+        assert_eq!(latest.last_modified.unwrap().timestamp(), 200);
+
+        assert!(latest_file(vec![], &Pattern::new("*").unwrap()).is_none());
+    }
+
+    #[test]
+    fn label() {
+        let config: ConfigSftpPing = serde_json::from_value(serde_json::json!({
+            "host": "backup.example.com",
+            "user": "foo",
+            "key_path": "id_ed25519",
+            "known_hosts_path": "known_hosts",
+            "directory": "/var/backups",
+            "glob": "*.bak",
+            "grace": "1 day",
+            "cron": "0 * * * * *",
+            "heartbeat_url": "http://localhost",
+        }))
+        .unwrap();
+        let absolute = SftpCheck::new(config.clone()).unwrap();
+        assert_eq!(
+            absolute.label(),
+            "sftp://foo@backup.example.com:22/var/backups/*.bak"
+        );
+        let relative = SftpCheck::new(ConfigSftpPing {
+            directory: "upload".into(),
+            ..config
+        })
+        .unwrap();
+        assert_eq!(
+            relative.label(),
+            "sftp://foo@backup.example.com:22/upload/*.bak"
+        );
+    }
+
+    /// Needs an OpenSSH server, e.g.
+    /// `docker run -p 2222:22 -v $PWD/id_ed25519.pub:/home/foo/.ssh/keys/id.pub:ro atmoz/sftp foo::1001:1001:upload`
+    /// and `SFTP_TEST_KEY=$PWD/id_ed25519 cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn openssh() {
+        let config: ConfigSftpPing = serde_json::from_value(serde_json::json!({
+            "host": "127.0.0.1",
+            "port": 2222,
+            "user": "foo",
+            "key_path": std::env::var("SFTP_TEST_KEY").unwrap(),
+            "directory": "upload",
+            "glob": "*.bak",
+            "grace": "1 minute",
+            "min_size": "4",
+            "cron": "0 * * * * *",
+            "heartbeat_url": "http://localhost",
+            "insecure_skip_host_key": true,
+        }))
+        .unwrap();
+        let check = SftpCheck::new(config.clone()).unwrap();
+
+        let session = connect(&config, None).unwrap();
+        let mut file = session
+            .sftp()
+            .unwrap()
+            .create(Path::new("upload/heart-beater.bak"))
+            .unwrap();
+        file.write_all(b"backup").unwrap();
+        drop(file);
+
+        let mut observation = Observation::default();
+        tick(
+            &config,
+            None,
+            &check.pattern,
+            &check.freshness,
+            &mut observation,
+        )
+        .unwrap();
+        assert_eq!(
+            observation.object_key.as_deref(),
+            Some("upload/heart-beater.bak")
+        );
+
+        let config = ConfigSftpPing {
+            min_size: Some(1024),
+            ..config
+        };
+        let check = SftpCheck::new(config.clone()).unwrap();
+        assert!(tick(
+            &config,
+            None,
+            &check.pattern,
+            &check.freshness,
+            &mut observation
+        )
+        .is_err());
+    }
+}
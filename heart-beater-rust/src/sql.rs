@@ -0,0 +1,307 @@
+use std::fmt;
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use log::debug;
+use sqlx::{
+    mysql::MySqlPoolOptions, postgres::PgPoolOptions, sqlite::SqlitePoolOptions, MySqlPool, PgPool,
+    Row, SqlitePool,
+};
+
+use crate::{
+    check::{Check, CheckOutcome, Observation},
+    config::ConfigSqlPing,
+};
+
+/// First column of the first row, decoded with whatever type the driver reported.
+#[derive(Debug, PartialEq)]
+enum SqlValue {
+    Null,
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bool(bool),
+    Timestamp(DateTime<Utc>),
+}
+
+impl fmt::Display for SqlValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SqlValue::Null => write!(f, "NULL"),
+            SqlValue::Int(v) => write!(f, "{v}"),
+            SqlValue::Float(v) => write!(f, "{v}"),
+            SqlValue::Text(v) => write!(f, "{v}"),
+            SqlValue::Bool(v) => write!(f, "{v}"),
+            SqlValue::Timestamp(v) => write!(f, "{}", v.to_rfc3339()),
+        }
+    }
+}
+
+/// Decodes the first column of `$row` as the first of these types the
+/// driver accepts. Each backend needs its own row type, hence a macro.
+/// Timestamps without a zone are taken as UTC.
+macro_rules! first_column {
+    ($row:expr) => {{
+        let row = $row;
+        if let Ok(v) = row.try_get::<Option<i64>, _>(0) {
+            Ok(v.map_or(SqlValue::Null, SqlValue::Int))
+        } else if let Ok(v) = row.try_get::<Option<i32>, _>(0) {
+            Ok(v.map_or(SqlValue::Null, |v| SqlValue::Int(v.into())))
+        } else if let Ok(v) = row.try_get::<Option<f64>, _>(0) {
+            Ok(v.map_or(SqlValue::Null, SqlValue::Float))
+        } else if let Ok(v) = row.try_get::<Option<bool>, _>(0) {
+            Ok(v.map_or(SqlValue::Null, SqlValue::Bool))
+        } else if let Ok(v) = row.try_get::<Option<DateTime<Utc>>, _>(0) {
+            Ok(v.map_or(SqlValue::Null, SqlValue::Timestamp))
+        } else if let Ok(v) = row.try_get::<Option<NaiveDateTime>, _>(0) {
+            Ok(v.map_or(SqlValue::Null, |v| SqlValue::Timestamp(v.and_utc())))
+        } else if let Ok(v) = row.try_get::<Option<NaiveDate>, _>(0) {
+            Ok(v.map_or(SqlValue::Null, |v| {
+                SqlValue::Timestamp(v.and_time(Default::default()).and_utc())
+            }))
+        } else {
+            row.try_get::<Option<String>, _>(0)
+                .map(|v| v.map_or(SqlValue::Null, SqlValue::Text))
+        }
+    }};
+}
+
+/// Native pool per backend; the generic `Any` driver cannot decode
+/// timestamp columns.
+enum Pool {
+    Postgres(PgPool),
+    MySql(MySqlPool),
+    Sqlite(SqlitePool),
+}
+
+impl Pool {
+    /// The connection itself is opened lazily.
+    fn connect_lazy(dsn: &str) -> Result<Self> {
+        let scheme = dsn.split_once(':').map_or("", |(scheme, _)| scheme);
+        Ok(match scheme {
+            "postgres" | "postgresql" => {
+                Pool::Postgres(PgPoolOptions::new().max_connections(1).connect_lazy(dsn)?)
+            }
+            "mysql" | "mariadb" => Pool::MySql(
+                MySqlPoolOptions::new()
+                    .max_connections(1)
+                    .connect_lazy(dsn)?,
+            ),
+            "sqlite" => Pool::Sqlite(
+                SqlitePoolOptions::new()
+                    .max_connections(1)
+                    .connect_lazy(dsn)?,
+            ),
+            _ => return Err(anyhow::anyhow!("unsupported DSN scheme {scheme:?}")),
+        })
+    }
+
+    async fn first_value(&self, query: &str) -> Result<SqlValue> {
+        let value = match self {
+            Pool::Postgres(pool) => first_column!(&sqlx::query(query).fetch_one(pool).await?),
+            Pool::MySql(pool) => first_column!(&sqlx::query(query).fetch_one(pool).await?),
+            Pool::Sqlite(pool) => first_column!(&sqlx::query(query).fetch_one(pool).await?),
+        };
+        Ok(value?)
+    }
+}
+
+impl SqlValue {
+    /// Integers are unix seconds; text accepts RFC 3339 and the usual
+    /// `YYYY-MM-DD HH:MM:SS[.f][+tz]` database renderings (UTC when no zone).
+    fn to_datetime(&self) -> Result<DateTime<Utc>> {
+        match self {
+            SqlValue::Timestamp(at) => Ok(*at),
+            SqlValue::Int(secs) => {
+                DateTime::from_timestamp(*secs, 0).ok_or(anyhow::anyhow!("{secs} is out of range"))
+            }
+            SqlValue::Float(secs) => DateTime::from_timestamp_millis((secs * 1000.0) as i64)
+                .ok_or(anyhow::anyhow!("{secs} is out of range")),
+            SqlValue::Text(s) => {
+                if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+                    return Ok(dt.to_utc());
+                }
+                if let Ok(dt) = DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f%#z") {
+                    return Ok(dt.to_utc());
+                }
+                Ok(NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f")?.and_utc())
+            }
+            v => Err(anyhow::anyhow!("{v} is not a timestamp")),
+        }
+    }
+}
+
+async fn tick(pool: &Pool, config: &ConfigSqlPing, observation: &mut Observation) -> Result<()> {
+    let value = pool.first_value(&config.query).await?;
+    debug!("query result => {value:?} / config={config:?}");
+    observation.value = Some(value.to_string());
+
+    if let Some(expected) = config.expected.as_ref() {
+        if value.to_string() != *expected {
+            return Err(anyhow::anyhow!("got {value}, expected {expected}"));
+        }
+    }
+
+    if let Some(grace) = config.grace {
+        let at = value.to_datetime()?;
+        observation.set_age(at);
+        if at.timestamp_millis() + grace.as_millis() as i64 <= Utc::now().timestamp_millis() {
+            return Err(anyhow::anyhow!("{at} is older than {grace:?}"));
+        }
+    }
+
+    if let SqlValue::Null = value {
+        if config.expected.is_none() && config.grace.is_none() {
+            return Err(anyhow::anyhow!("query returned NULL"));
+        }
+    }
+
+    Ok(())
+}
+
+pub struct SqlCheck {
+    pool: Pool,
+    config: ConfigSqlPing,
+}
+
+impl SqlCheck {
+    /// Reads the DSN from `dsn_env`; the connection itself is opened lazily.
+    pub fn new(config: ConfigSqlPing) -> Result<Self> {
+        let dsn = std::env::var(&config.dsn_env)
+            .map_err(|e| anyhow::anyhow!("failed to read {}. {e}", config.dsn_env))?;
+        let pool = Pool::connect_lazy(&dsn)?;
+        Ok(Self { pool, config })
+    }
+}
+
+impl Check for SqlCheck {
+    fn kind(&self) -> &'static str {
+        "sql"
+    }
+
+    fn label(&self) -> String {
+        self.config.label()
+    }
+
+    async fn run(&self) -> CheckOutcome {
+        let mut observation = Observation::default();
+        let result = tick(&self.pool, &self.config, &mut observation).await;
+        CheckOutcome::from(result).with_observation(observation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datetime() {
+        let expected = DateTime::parse_from_rfc3339("2024-05-01T12:34:56Z")
+            .unwrap()
+            .to_utc();
+        for v in [
+            SqlValue::Int(1714566896),
+            SqlValue::Text("2024-05-01T12:34:56Z".into()),
+            SqlValue::Text("2024-05-01 12:34:56".into()),
+            SqlValue::Text("2024-05-01 21:34:56+09".into()),
+            SqlValue::Text("2024-05-01 12:34:56.000+00:00".into()),
+        ] {
+            assert_eq!(v.to_datetime().unwrap(), expected, "{v:?}");
+        }
+        assert!(SqlValue::Null.to_datetime().is_err());
+    }
+
+    fn config(query: &str, grace: &str) -> ConfigSqlPing {
+        serde_json::from_value(serde_json::json!({
+            "dsn_env": "UNUSED",
+            "query": query,
+            "grace": grace,
+            "cron": "0 * * * * *",
+            "heartbeat_url": "http://localhost",
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sqlite() {
+        let pool = Pool::connect_lazy("sqlite::memory:").unwrap();
+        let Pool::Sqlite(sqlite) = &pool else {
+            unreachable!()
+        };
+        sqlx::query("CREATE TABLE orders (created_at DATETIME)")
+            .execute(sqlite)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO orders VALUES (datetime('now', '-10 minutes'))")
+            .execute(sqlite)
+            .await
+            .unwrap();
+
+        let config = config("SELECT max(created_at) FROM orders", "1 hour");
+        let mut observation = Observation::default();
+        assert!(tick(&pool, &config, &mut observation).await.is_ok());
+        assert!(observation.value.is_some());
+
+        let value = pool
+            .first_value("SELECT created_at FROM orders")
+            .await
+            .unwrap();
+        assert!(matches!(value, SqlValue::Timestamp(_)), "{value:?}");
+
+        let config = ConfigSqlPing {
+            grace: Some(std::time::Duration::from_secs(60)),
+            ..config
+        };
+        assert!(tick(&pool, &config, &mut observation).await.is_err());
+
+        let config = ConfigSqlPing {
+            query: "SELECT count(*) FROM orders".into(),
+            grace: None,
+            expected: Some("1".into()),
+            ..config
+        };
+        assert!(tick(&pool, &config, &mut observation).await.is_ok());
+        assert!(Pool::connect_lazy("oracle://db").is_err());
+    }
+
+    /// Native timestamp columns, e.g. with
+    /// `docker run -p 5432:5432 -e POSTGRES_PASSWORD=pw postgres` and
+    /// `docker run -p 3306:3306 -e MYSQL_ROOT_PASSWORD=pw -e MYSQL_DATABASE=test mysql`, then
+    /// `SQL_TEST_DSNS=postgres://postgres:pw@127.0.0.1/postgres,mysql://root:pw@127.0.0.1/test cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn native_timestamps() {
+        for dsn in std::env::var("SQL_TEST_DSNS").unwrap().split(',') {
+            let pool = Pool::connect_lazy(dsn).unwrap();
+            let (create, insert) = match &pool {
+                Pool::Postgres(_) => (
+                    "CREATE TEMPORARY TABLE orders (created_at TIMESTAMPTZ, updated_at TIMESTAMP)",
+                    "INSERT INTO orders VALUES (now() - interval '10 minutes', now() at time zone 'utc' - interval '10 minutes')",
+                ),
+                Pool::MySql(_) => (
+                    "CREATE TEMPORARY TABLE orders (created_at TIMESTAMP, updated_at DATETIME)",
+                    "INSERT INTO orders VALUES (now() - interval 10 minute, utc_timestamp() - interval 10 minute)",
+                ),
+                Pool::Sqlite(_) => unreachable!(),
+            };
+            for statement in [create, insert] {
+                match &pool {
+                    Pool::Postgres(pool) => sqlx::query(statement).execute(pool).await.map(|_| ()),
+                    Pool::MySql(pool) => sqlx::query(statement).execute(pool).await.map(|_| ()),
+                    Pool::Sqlite(_) => unreachable!(),
+                }
+                .unwrap();
+            }
+            for query in [
+                "SELECT max(created_at) FROM orders",
+                "SELECT max(updated_at) FROM orders",
+            ] {
+                let mut observation = Observation::default();
+                let result = tick(&pool, &config(query, "1 hour"), &mut observation).await;
+                assert!(result.is_ok(), "{dsn} {query} {result:?}");
+                let result = tick(&pool, &config(query, "1 minute"), &mut observation).await;
+                assert!(result.is_err(), "{dsn} {query}");
+            }
+        }
+    }
+}
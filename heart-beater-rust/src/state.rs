@@ -0,0 +1,143 @@
+use std::sync::{Arc, Mutex};
+
+/// Consecutive success/failure counters of a single check.
+///
+/// A check starts healthy. It only turns unhealthy after `failure_threshold`
+/// consecutive failures and only recovers after `success_threshold`
+/// consecutive successes, so a single flaky response does not flip it.
+#[derive(Debug)]
+pub struct CheckState {
+    failure_threshold: u32,
+    success_threshold: u32,
+    consecutive_failures: u32,
+    consecutive_successes: u32,
+    healthy: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Transition {
+    None,
+    Down,
+    Up,
+}
+
+impl CheckState {
+    pub fn new(failure_threshold: u32, success_threshold: u32) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            success_threshold: success_threshold.max(1),
+            consecutive_failures: 0,
+            consecutive_successes: 0,
+            healthy: true,
+        }
+    }
+
+    pub fn record(&mut self, ok: bool) -> Transition {
+        if ok {
+            self.consecutive_successes += 1;
+            self.consecutive_failures = 0;
+            if !self.healthy && self.consecutive_successes >= self.success_threshold {
+                self.healthy = true;
+                return Transition::Up;
+            }
+        } else {
+            self.consecutive_failures += 1;
+            self.consecutive_successes = 0;
+            if self.healthy && self.consecutive_failures >= self.failure_threshold {
+                self.healthy = false;
+                return Transition::Down;
+            }
+        }
+        Transition::None
+    }
+
+    pub fn healthy(&self) -> bool {
+        self.healthy
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    pub fn consecutive_successes(&self) -> u32 {
+        self.consecutive_successes
+    }
+}
+
+/// Snapshot of one check, see [`crate::Scheduler::status`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckStatus {
+    pub name: String,
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub consecutive_successes: u32,
+}
+
+/// State of one check as shared with its job.
+pub type SharedState = Arc<Mutex<CheckState>>;
+
+/// State of every check of one scheduler, for status output.
+#[derive(Clone, Default)]
+pub struct States {
+    checks: Arc<Mutex<Vec<(String, SharedState)>>>,
+}
+
+impl States {
+    /// Fails for a name that is already registered, so that two checks never
+    /// share one status or log name.
+    pub fn register(&self, name: String, state: SharedState) -> anyhow::Result<()> {
+        let mut checks = self.checks.lock().unwrap();
+        if checks.iter().any(|(registered, _)| *registered == name) {
+            return Err(anyhow::anyhow!("duplicate check name {name:?}"));
+        }
+        checks.push((name, state));
+        Ok(())
+    }
+
+    pub fn status(&self) -> Vec<CheckStatus> {
+        self.checks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, state)| {
+                let state = state.lock().unwrap();
+                CheckStatus {
+                    name: name.clone(),
+                    healthy: state.healthy(),
+                    consecutive_failures: state.consecutive_failures(),
+                    consecutive_successes: state.consecutive_successes(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds() {
+        let mut state = CheckState::new(3, 2);
+        assert_eq!(state.record(false), Transition::None);
+        assert_eq!(state.record(false), Transition::None);
+        assert!(state.healthy());
+        assert_eq!(state.record(true), Transition::None);
+        assert_eq!(state.record(false), Transition::None);
+        assert_eq!(state.record(false), Transition::None);
+        assert_eq!(state.record(false), Transition::Down);
+        assert!(!state.healthy());
+        assert_eq!(state.record(false), Transition::None);
+        assert_eq!(state.record(true), Transition::None);
+        assert!(!state.healthy());
+        assert_eq!(state.record(true), Transition::Up);
+        assert!(state.healthy());
+    }
+
+    #[test]
+    fn default_thresholds_flip_immediately() {
+        let mut state = CheckState::new(1, 1);
+        assert_eq!(state.record(false), Transition::Down);
+        assert_eq!(state.record(true), Transition::Up);
+    }
+}
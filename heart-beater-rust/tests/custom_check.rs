@@ -0,0 +1,63 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use axum::{extract::State, routing::get, Router};
+use heart_beater_rust::{config::ConfigCheck, Check, CheckOutcome, Observation, Scheduler};
+
+struct Counting(Arc<AtomicUsize>);
+
+impl Check for Counting {
+    fn label(&self) -> String {
+        "counting".into()
+    }
+
+    async fn run(&self) -> CheckOutcome {
+        let n = self.0.fetch_add(1, Ordering::SeqCst);
+        CheckOutcome::ok().with_observation(Observation {
+            value: Some(n.to_string()),
+            ..Default::default()
+        })
+    }
+}
+
+#[tokio::test]
+async fn custom_check_pings_heartbeat() {
+    let heartbeats = Arc::new(AtomicUsize::new(0));
+    let app = Router::new()
+        .route(
+            "/",
+            get(|State(heartbeats): State<Arc<AtomicUsize>>| async move {
+                heartbeats.fetch_add(1, Ordering::SeqCst);
+            }),
+        )
+        .with_state(heartbeats.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let heartbeat_url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let runs = Arc::new(AtomicUsize::new(0));
+    let mut scheduler = Scheduler::builder()
+        .check(
+            ConfigCheck::new("* * * * * *", heartbeat_url),
+            Counting(runs.clone()),
+        )
+        .build()
+        .await
+        .unwrap();
+    scheduler.start().await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+    let status = scheduler.status();
+    assert_eq!(status.len(), 1);
+    assert_eq!(status[0].name, "counting");
+    assert!(status[0].healthy);
+    assert!(status[0].consecutive_successes >= 1);
+    scheduler
+        .shutdown(std::time::Duration::from_secs(5))
+        .await
+        .unwrap();
+
+    assert!(runs.load(Ordering::SeqCst) >= 1);
+    assert!(heartbeats.load(Ordering::SeqCst) >= 1);
+}
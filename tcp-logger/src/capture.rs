@@ -0,0 +1,514 @@
+use base64::Engine;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::oneshot;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum CaptureFormat {
+    /// one JSON object per chunk, payload in base64
+    Jsonl,
+    /// pcap-ng with synthesized IP/TCP frames, readable by Wireshark
+    Pcapng,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// client -> server
+    Inbound,
+    /// server -> client
+    Outbound,
+}
+
+impl Direction {
+    pub fn other(self) -> Direction {
+        match self {
+            Direction::Inbound => Direction::Outbound,
+            Direction::Outbound => Direction::Inbound,
+        }
+    }
+}
+
+/// Capture file shared by all connections. Records are written by a
+/// dedicated thread so connections never wait for the disk.
+pub struct Capture {
+    format: CaptureFormat,
+    commands: mpsc::Sender<Command>,
+}
+
+enum Command {
+    Write(Vec<u8>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// How long written records may stay buffered.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+impl Capture {
+    pub fn create(path: &str, format: CaptureFormat) -> io::Result<Capture> {
+        let mut out = BufWriter::new(File::create(path)?);
+        if let CaptureFormat::Pcapng = format {
+            pcapng::write_header(&mut out)?;
+            out.flush()?;
+        }
+        let (commands, received) = mpsc::channel();
+        thread::Builder::new()
+            .name("capture".to_string())
+            .spawn(move || writer(out, received))?;
+        Ok(Capture { format, commands })
+    }
+
+    /// Waits until the records sent so far are on disk.
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.commands.send(Command::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    /// Unix domain socket ends have no address; they are written as
+    /// 127.0.0.1 (client, the connection id picks the port) and 127.0.0.2.
+    pub fn connection(
+        self: &Arc<Self>,
+        id: u64,
+        client: Option<SocketAddr>,
+        server: Option<SocketAddr>,
+    ) -> ConnectionCapture {
+        let conn = ConnectionCapture {
+            capture: self.clone(),
+            id,
+            client: client.unwrap_or(([127, 0, 0, 1], 10000 + (id % 50000) as u16).into()),
+            server: server.unwrap_or(([127, 0, 0, 2], 1).into()),
+            udp: false,
+            inbound: AtomicU64::new(0),
+            outbound: AtomicU64::new(0),
+        };
+        conn.report(conn.segment(tcp::SYN, Direction::Inbound, &[]));
+        conn.report(conn.segment(tcp::SYN | tcp::ACK, Direction::Outbound, &[]));
+        conn.report(conn.segment(tcp::ACK, Direction::Inbound, &[]));
+        conn
+    }
+
+    /// Datagrams exchanged between one UDP client and the upstream.
+    pub fn session(
+        self: &Arc<Self>,
+        id: u64,
+        client: SocketAddr,
+        server: SocketAddr,
+    ) -> ConnectionCapture {
+        ConnectionCapture {
+            capture: self.clone(),
+            id,
+            client,
+            server,
+            udp: true,
+            inbound: AtomicU64::new(0),
+            outbound: AtomicU64::new(0),
+        }
+    }
+
+    fn write(&self, f: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> io::Result<()> {
+        let mut buf = Vec::new();
+        f(&mut buf)?;
+        self.commands
+            .send(Command::Write(buf))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "capture writer stopped"))
+    }
+}
+
+/// Writes the records until every `Capture` handle is gone, flushing at
+/// least every `FLUSH_INTERVAL`.
+fn writer(mut out: BufWriter<File>, commands: mpsc::Receiver<Command>) {
+    let mut flushed = Instant::now();
+    loop {
+        let timeout = (flushed + FLUSH_INTERVAL).saturating_duration_since(Instant::now());
+        let result = match commands.recv_timeout(timeout) {
+            Ok(Command::Write(buf)) => out.write_all(&buf),
+            Ok(Command::Flush(done)) => {
+                flushed = Instant::now();
+                let result = out.flush();
+                let _ = done.send(());
+                result
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                flushed = Instant::now();
+                out.flush()
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        if let Err(e) = result {
+            println!("Failed to write capture; error={}", e);
+        }
+    }
+    if let Err(e) = out.flush() {
+        println!("Failed to write capture; error={}", e);
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    connection: u64,
+    direction: Direction,
+    timestamp: f64,
+    offset: u64,
+    payload: &'a str,
+}
+
+/// Writes the chunks of one connection, keeping track of the byte offset in
+/// each direction.
+pub struct ConnectionCapture {
+    capture: Arc<Capture>,
+    id: u64,
+    client: SocketAddr,
+    server: SocketAddr,
+    /// datagrams instead of a TCP stream, no handshake in pcapng
+    udp: bool,
+    inbound: AtomicU64,
+    outbound: AtomicU64,
+}
+
+impl ConnectionCapture {
+    pub fn chunk(&self, direction: Direction, buf: &[u8]) {
+        let result = match self.capture.format {
+            CaptureFormat::Jsonl => self.record(direction, buf),
+            CaptureFormat::Pcapng if self.udp => self.datagram(direction, buf),
+            CaptureFormat::Pcapng => buf
+                .chunks(tcp::MAX_SEGMENT)
+                .try_for_each(|segment| self.segment(tcp::PSH | tcp::ACK, direction, segment)),
+        };
+        self.report(result);
+    }
+
+    fn record(&self, direction: Direction, buf: &[u8]) -> io::Result<()> {
+        let offset = self
+            .offset(direction)
+            .fetch_add(buf.len() as u64, Ordering::SeqCst);
+        let payload = base64::engine::general_purpose::STANDARD.encode(buf);
+        let record = Record {
+            connection: self.id,
+            direction,
+            timestamp: now().as_secs_f64(),
+            offset,
+            payload: &payload,
+        };
+        self.capture.write(|out| {
+            serde_json::to_writer(&mut *out, &record)?;
+            out.write_all(b"\n")
+        })
+    }
+
+    fn offset(&self, direction: Direction) -> &AtomicU64 {
+        match direction {
+            Direction::Inbound => &self.inbound,
+            Direction::Outbound => &self.outbound,
+        }
+    }
+
+    /// SYN and FIN count as one byte of sequence space.
+    fn segment(&self, flags: u8, direction: Direction, payload: &[u8]) -> io::Result<()> {
+        if let CaptureFormat::Jsonl = self.capture.format {
+            return Ok(());
+        }
+        let (src, dst, other) = match direction {
+            Direction::Inbound => (self.client, self.server, Direction::Outbound),
+            Direction::Outbound => (self.server, self.client, Direction::Inbound),
+        };
+        let consumed = payload.len() as u64 + u64::from(flags & (tcp::SYN | tcp::FIN) != 0);
+        let seq = self.offset(direction).fetch_add(consumed, Ordering::SeqCst);
+        let ack = self.offset(other).load(Ordering::SeqCst);
+        let ack = if flags & tcp::ACK != 0 { ack } else { 0 };
+        let frame = tcp::frame(src, dst, seq as u32, ack as u32, flags, payload);
+        self.capture
+            .write(|out| pcapng::write_packet(out, now().as_micros() as u64, &frame, frame.len()))
+    }
+
+    fn datagram(&self, direction: Direction, payload: &[u8]) -> io::Result<()> {
+        let (src, dst) = match direction {
+            Direction::Inbound => (self.client, self.server),
+            Direction::Outbound => (self.server, self.client),
+        };
+        let captured = payload.len().min(udp::MAX_PAYLOAD);
+        if captured < payload.len() {
+            println!(
+                "Datagram of {} bytes truncated to {} in the capture",
+                payload.len(),
+                captured
+            );
+        }
+        let frame = udp::frame(src, dst, &payload[..captured]);
+        let original = frame.len() + payload.len() - captured;
+        self.capture
+            .write(|out| pcapng::write_packet(out, now().as_micros() as u64, &frame, original))
+    }
+
+    fn report(&self, result: io::Result<()>) {
+        if let Err(e) = result {
+            println!("Failed to write capture; error={}", e);
+        }
+    }
+}
+
+impl Drop for ConnectionCapture {
+    fn drop(&mut self) {
+        if self.udp {
+            return;
+        }
+        self.report(self.segment(tcp::FIN | tcp::ACK, Direction::Inbound, &[]));
+        self.report(self.segment(tcp::FIN | tcp::ACK, Direction::Outbound, &[]));
+        self.report(self.segment(tcp::ACK, Direction::Inbound, &[]));
+    }
+}
+
+fn now() -> std::time::Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+mod tcp {
+    use super::*;
+
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+
+    /// Keeps the IPv4 total length within u16.
+    pub const MAX_SEGMENT: usize = 65000;
+
+    pub fn frame(
+        src: SocketAddr,
+        dst: SocketAddr,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut segment = Vec::with_capacity(20 + payload.len());
+        segment.extend_from_slice(&src.port().to_be_bytes());
+        segment.extend_from_slice(&dst.port().to_be_bytes());
+        segment.extend_from_slice(&seq.to_be_bytes());
+        segment.extend_from_slice(&ack.to_be_bytes());
+        segment.push(5 << 4);
+        segment.push(flags);
+        segment.extend_from_slice(&u16::MAX.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 0]);
+        segment.extend_from_slice(payload);
+        ip::packet(src.ip(), dst.ip(), ip::TCP, segment, 16)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn checksum_validates() {
+            let frame = frame(
+                "10.0.0.1:40000".parse().unwrap(),
+                "10.0.0.2:5432".parse().unwrap(),
+                1,
+                1,
+                PSH | ACK,
+                b"hello",
+            );
+            assert_eq!(frame.len(), 45);
+            assert_eq!(ip::checksum(&[&frame[..20]]), 0);
+        }
+    }
+}
+
+mod udp {
+    use super::*;
+
+    /// Largest payload whose frame fits the IPv4 total length (and the IPv6
+    /// payload length); UDP over IPv4 cannot carry more anyway.
+    pub const MAX_PAYLOAD: usize = 65535 - 20 - 8;
+
+    pub fn frame(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+        let mut datagram = Vec::with_capacity(8 + payload.len());
+        datagram.extend_from_slice(&src.port().to_be_bytes());
+        datagram.extend_from_slice(&dst.port().to_be_bytes());
+        datagram.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(payload);
+        ip::packet(src.ip(), dst.ip(), ip::UDP, datagram, 6)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn checksum_validates() {
+            let frame = frame(
+                "10.0.0.1:40000".parse().unwrap(),
+                "10.0.0.2:53".parse().unwrap(),
+                b"query",
+            );
+            assert_eq!(frame.len(), 33);
+            let mut pseudo = frame[12..20].to_vec();
+            pseudo.extend_from_slice(&[0, ip::UDP, 0, 13]);
+            assert_eq!(ip::checksum(&[&pseudo, &frame[20..]]), 0);
+        }
+
+        #[test]
+        fn large_datagram() {
+            let payload = vec![0xab; MAX_PAYLOAD];
+            let frame = frame(
+                "10.0.0.1:40000".parse().unwrap(),
+                "10.0.0.2:53".parse().unwrap(),
+                &payload,
+            );
+            assert_eq!(frame.len(), 65535);
+            assert_eq!(&frame[2..4], &[0xff, 0xff]);
+            assert_eq!(ip::checksum(&[&frame[..20]]), 0);
+        }
+    }
+}
+
+mod ip {
+    use super::*;
+
+    pub const TCP: u8 = 6;
+    pub const UDP: u8 = 17;
+
+    /// Fills in the transport checksum at `checksum_at` and prepends an IP
+    /// header: IPv4 when both ends are IPv4, IPv6 (with mapped addresses)
+    /// otherwise.
+    pub fn packet(
+        src: IpAddr,
+        dst: IpAddr,
+        protocol: u8,
+        mut segment: Vec<u8>,
+        checksum_at: usize,
+    ) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(40 + segment.len());
+        let set_checksum = |segment: &mut Vec<u8>, pseudo: &[u8]| {
+            let sum = match checksum(&[pseudo, segment]) {
+                // zero means "no checksum" for UDP
+                0 if protocol == UDP => 0xffff,
+                sum => sum,
+            };
+            segment[checksum_at..checksum_at + 2].copy_from_slice(&sum.to_be_bytes());
+        };
+        match (src, dst) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => {
+                let mut pseudo = [0u8; 12];
+                pseudo[..4].copy_from_slice(&s.octets());
+                pseudo[4..8].copy_from_slice(&d.octets());
+                pseudo[9] = protocol;
+                pseudo[10..].copy_from_slice(&(segment.len() as u16).to_be_bytes());
+                set_checksum(&mut segment, &pseudo);
+
+                frame.extend_from_slice(&[0x45, 0]);
+                frame.extend_from_slice(&(20 + segment.len() as u16).to_be_bytes());
+                frame.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+                frame.extend_from_slice(&s.octets());
+                frame.extend_from_slice(&d.octets());
+                let sum = checksum(&[&frame]);
+                frame[10..12].copy_from_slice(&sum.to_be_bytes());
+            }
+            (s, d) => {
+                let to_v6 = |ip: IpAddr| match ip {
+                    IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                    IpAddr::V6(ip) => ip,
+                };
+                let (s, d) = (to_v6(s), to_v6(d));
+                let mut pseudo = [0u8; 40];
+                pseudo[..16].copy_from_slice(&s.octets());
+                pseudo[16..32].copy_from_slice(&d.octets());
+                pseudo[32..36].copy_from_slice(&(segment.len() as u32).to_be_bytes());
+                pseudo[39] = protocol;
+                set_checksum(&mut segment, &pseudo);
+
+                frame.extend_from_slice(&[0x60, 0, 0, 0]);
+                frame.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+                frame.extend_from_slice(&[protocol, 64]);
+                frame.extend_from_slice(&s.octets());
+                frame.extend_from_slice(&d.octets());
+            }
+        }
+        frame.extend_from_slice(&segment);
+        frame
+    }
+
+    /// RFC 1071 internet checksum over the concatenation of `parts`.
+    pub fn checksum(parts: &[&[u8]]) -> u16 {
+        let mut sum = 0u32;
+        let mut odd: Option<u8> = None;
+        for byte in parts.iter().flat_map(|part| part.iter()) {
+            match odd.take() {
+                Some(high) => sum += u32::from(u16::from_be_bytes([high, *byte])),
+                None => odd = Some(*byte),
+            }
+        }
+        if let Some(high) = odd {
+            sum += u32::from(u16::from_be_bytes([high, 0]));
+        }
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+}
+
+mod pcapng {
+    use super::*;
+
+    /// Raw IP packets, no link layer.
+    const LINKTYPE_RAW: u16 = 101;
+
+    fn write_block(out: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let padding = (4 - body.len() % 4) % 4;
+        let total = (12 + body.len() + padding) as u32;
+        out.write_all(&block_type.to_le_bytes())?;
+        out.write_all(&total.to_le_bytes())?;
+        out.write_all(body)?;
+        out.write_all(&[0; 3][..padding])?;
+        out.write_all(&total.to_le_bytes())
+    }
+
+    /// Section header and a single interface with microsecond timestamps.
+    pub fn write_header(out: &mut impl Write) -> io::Result<()> {
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&0x1A2B3C4Du32.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(out, 0x0A0D0D0A, &shb)?;
+
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        write_block(out, 1, &idb)
+    }
+
+    /// `original` is the length of the packet before it was truncated to `frame`.
+    pub fn write_packet(
+        out: &mut impl Write,
+        micros: u64,
+        frame: &[u8],
+        original: usize,
+    ) -> io::Result<()> {
+        let mut epb = Vec::with_capacity(20 + frame.len());
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(micros as u32).to_le_bytes());
+        epb.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(original as u32).to_le_bytes());
+        epb.extend_from_slice(frame);
+        write_block(out, 6, &epb)
+    }
+}
@@ -0,0 +1,252 @@
+use super::{describe, Decoder, Message};
+use crate::capture::Direction;
+use crate::render::is_printable;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Longest request / status line plus headers accepted before giving up.
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Whether each request still waiting for its response was a HEAD, filled
+/// by the inbound decoder and taken by the outbound one. Responses to
+/// requests that were not decoded count as GET.
+pub type Requests = Arc<Mutex<VecDeque<bool>>>;
+
+/// Bodies are printed as their bytes arrive instead of being buffered whole.
+enum Body {
+    /// waiting for the next request / status line
+    None,
+    /// bytes of the body still to come
+    Length(usize),
+    /// waiting for the next chunk size line
+    Chunked,
+    /// bytes of the current chunk still to come, then its `\r\n`
+    Chunk(usize),
+    /// response without length, ends when the connection closes
+    UntilClose,
+}
+
+pub struct Http {
+    direction: Direction,
+    body: Body,
+    requests: Requests,
+}
+
+impl Http {
+    pub fn new(direction: Direction, requests: Requests) -> Http {
+        Http {
+            direction,
+            body: Body::None,
+            requests,
+        }
+    }
+
+    fn head(&mut self, buf: &[u8]) -> Result<Option<Message>, String> {
+        if !buf[0].is_ascii_uppercase() {
+            return Err(format!("unexpected byte 0x{:02x}", buf[0]));
+        }
+        let end = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => end,
+            None if buf.len() > MAX_HEAD => return Err("header too long".to_string()),
+            None => return Ok(None),
+        };
+        let head = std::str::from_utf8(&buf[..end]).map_err(|_| "header is not UTF-8")?;
+        let mut lines = head.split("\r\n");
+        let start = lines.next().unwrap_or_default();
+        let is_response = start.starts_with("HTTP/");
+        let status = if is_response {
+            start.split(' ').nth(1).and_then(|s| s.parse::<u16>().ok())
+        } else {
+            None
+        };
+        match (self.direction, is_response) {
+            (Direction::Inbound, false) if start.split(' ').count() == 3 => {}
+            (Direction::Outbound, true) if status.is_some() => {}
+            _ => return Err(format!("unexpected start line {:?}", start)),
+        }
+
+        let mut length = None;
+        let mut chunked = false;
+        for header in lines.clone() {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| format!("invalid header {:?}", header))?;
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                length = Some(value.parse::<usize>().map_err(|e| e.to_string())?);
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.to_ascii_lowercase().ends_with("chunked");
+            }
+        }
+        let mut requests = self.requests.lock().unwrap();
+        let head_request = match status {
+            None => {
+                requests.push_back(start.starts_with("HEAD "));
+                false
+            }
+            // informational responses come before the final one
+            Some(100..=199) => false,
+            Some(_) => requests.pop_front().unwrap_or(false),
+        };
+        let no_body = head_request || matches!(status, Some(100..=199 | 204 | 304));
+        self.body = match (chunked, length) {
+            _ if no_body => Body::None,
+            (true, _) => Body::Chunked,
+            (false, Some(0)) => Body::None,
+            (false, Some(length)) => Body::Length(length),
+            (false, None) if is_response => Body::UntilClose,
+            (false, None) => Body::None,
+        };
+
+        Ok(Some(Message {
+            len: end + 4,
+            lines: std::iter::once(start)
+                .chain(lines)
+                .map(|line| line.to_string())
+                .collect(),
+        }))
+    }
+}
+
+/// Printable bodies are shown as text lines.
+fn body(buf: &[u8]) -> Vec<String> {
+    if !is_printable(buf) {
+        return vec![describe(buf)];
+    }
+    String::from_utf8_lossy(buf)
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .collect()
+}
+
+impl Decoder for Http {
+    fn parse(&mut self, buf: &[u8]) -> Result<Option<Message>, String> {
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        match self.body {
+            Body::None => self.head(buf),
+            Body::Length(remaining) => {
+                let len = remaining.min(buf.len());
+                self.body = match remaining - len {
+                    0 => Body::None,
+                    remaining => Body::Length(remaining),
+                };
+                Ok(Some(Message {
+                    len,
+                    lines: body(&buf[..len]),
+                }))
+            }
+            Body::UntilClose => Ok(Some(Message {
+                len: buf.len(),
+                lines: body(buf),
+            })),
+            Body::Chunked => {
+                let Some(end) = buf.windows(2).position(|w| w == b"\r\n") else {
+                    return Ok(None);
+                };
+                let size = std::str::from_utf8(&buf[..end])
+                    .ok()
+                    .and_then(|line| line.split(';').next())
+                    .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+                    .ok_or("invalid chunk size")?;
+                if size == 0 {
+                    // the size line is followed by optional trailers and an empty line
+                    let Some(trailers) = buf[end..].windows(4).position(|w| w == b"\r\n\r\n")
+                    else {
+                        return Ok(None);
+                    };
+                    self.body = Body::None;
+                    return Ok(Some(Message {
+                        len: end + trailers + 4,
+                        lines: vec!["[end of chunked body]".to_string()],
+                    }));
+                }
+                self.body = Body::Chunk(size);
+                Ok(Some(Message {
+                    len: end + 2,
+                    lines: Vec::new(),
+                }))
+            }
+            Body::Chunk(0) => {
+                if buf.len() < 2 {
+                    return Ok(None);
+                }
+                if &buf[..2] != b"\r\n" {
+                    return Err("chunk not terminated".to_string());
+                }
+                self.body = Body::Chunked;
+                Ok(Some(Message {
+                    len: 2,
+                    lines: Vec::new(),
+                }))
+            }
+            Body::Chunk(remaining) => {
+                let len = remaining.min(buf.len());
+                self.body = Body::Chunk(remaining - len);
+                Ok(Some(Message {
+                    len,
+                    lines: body(&buf[..len]),
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_and_chunked_response() {
+        let requests = Requests::default();
+        let mut request = Http::new(Direction::Inbound, requests.clone());
+        let buf = b"POST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 2\r\n\r\nhi";
+        let head = request.parse(buf).unwrap().unwrap();
+        assert_eq!(
+            head.lines,
+            vec!["POST /a HTTP/1.1", "Host: x", "Content-Length: 2"]
+        );
+        // the body is printed as it arrives
+        let body = request
+            .parse(&buf[head.len..buf.len() - 1])
+            .unwrap()
+            .unwrap();
+        assert_eq!((body.len, body.lines), (1, vec!["h".to_string()]));
+        let body = request.parse(&buf[buf.len() - 1..]).unwrap().unwrap();
+        assert_eq!(body.lines, vec!["i"]);
+        request.parse(b"HEAD / HTTP/1.1\r\n\r\n").unwrap().unwrap();
+
+        let mut response = Http::new(Direction::Outbound, requests);
+        let buf = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n";
+        let head = response.parse(buf).unwrap().unwrap();
+        assert_eq!(head.len, buf.len());
+        // the body of the POST response
+        assert_eq!(response.parse(b"hello").unwrap().unwrap().len, 5);
+        // the HEAD response has a length but no body
+        response.parse(buf).unwrap().unwrap();
+        assert!(response.parse(b"HTTP/1.1 ").unwrap().is_none());
+
+        let buf = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        let mut offset = 0;
+        let mut lines = Vec::new();
+        while let Some(message) = response.parse(&buf[offset..]).unwrap() {
+            offset += message.len;
+            lines.extend(message.lines);
+        }
+        assert_eq!(offset, buf.len());
+        assert_eq!(
+            lines,
+            vec![
+                "HTTP/1.1 200 OK",
+                "Transfer-Encoding: chunked",
+                "abc",
+                "[end of chunked body]"
+            ]
+        );
+
+        assert!(Http::new(Direction::Inbound, Requests::default())
+            .parse(b"\x16\x03\x01\x02\x00\r\n\r\n")
+            .is_err());
+    }
+}
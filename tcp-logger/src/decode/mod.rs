@@ -0,0 +1,236 @@
+mod http;
+mod mysql;
+mod postgres;
+mod redis;
+
+use crate::capture::Direction;
+use crate::render::is_printable;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// no decoding, print chunks as they are
+    Raw,
+    /// HTTP/1.x request / response lines and headers
+    Http,
+    /// Redis RESP commands and replies
+    Redis,
+    /// PostgreSQL frontend / backend messages
+    Postgres,
+    /// MySQL client / server packets
+    Mysql,
+}
+
+/// One message parsed from the start of the buffered stream.
+pub struct Message {
+    /// bytes consumed from the buffer, more than buffered for messages that
+    /// are only summarized; the rest is skipped as it arrives
+    pub len: usize,
+    /// empty for the first part of a message still arriving
+    pub lines: Vec<String>,
+}
+
+/// Parses the byte stream of one direction of a connection.
+trait Decoder: Send {
+    /// Parses one message from the start of `buf`; `Ok(None)` when more
+    /// bytes are needed.
+    fn parse(&mut self, buf: &[u8]) -> Result<Option<Message>, String>;
+}
+
+/// Most bytes buffered for one message. Decoders summarize larger messages
+/// from their header or stream them; otherwise the stream is printed raw.
+const MAX_BUFFERED: usize = 1024 * 1024;
+
+pub enum Decoded {
+    Lines(Vec<String>),
+    /// bytes to print as they are, ending where the bytes `held` back start
+    Raw(Vec<u8>),
+}
+
+/// Raw bytes without a newline are released anyway past this size, so binary
+/// streams are not held back until the connection closes.
+const MAX_PARTIAL_LINE: usize = 64 * 1024;
+
+/// Reassembles chunks into messages. Once a message fails to parse the rest
+/// of the stream is printed raw, since message boundaries are lost.
+pub struct StreamDecoder {
+    decoder: Option<Box<dyn Decoder>>,
+    buffer: Vec<u8>,
+    /// bytes of a summarized message still to come
+    skip: usize,
+    /// raw output ends on line boundaries, see `raw`
+    whole_lines: bool,
+    partial_line: Vec<u8>,
+}
+
+/// Decoders for the inbound and outbound direction of one connection; HTTP
+/// responses depend on the method of their request.
+///
+/// With `whole_lines` the last partial line of raw output is held back until
+/// the next chunk or `finish`, so a pattern matched against the output
+/// (`--redact`) sees lines split across reads in one piece.
+pub fn pair(protocol: Protocol, whole_lines: bool) -> (StreamDecoder, StreamDecoder) {
+    let requests = http::Requests::default();
+    let decoder = |direction: Direction| -> Option<Box<dyn Decoder>> {
+        match protocol {
+            Protocol::Raw => None,
+            Protocol::Http => Some(Box::new(http::Http::new(direction, requests.clone()))),
+            Protocol::Redis => Some(Box::new(redis::Redis::default())),
+            Protocol::Postgres => Some(Box::new(postgres::Postgres::new(direction))),
+            Protocol::Mysql => Some(Box::new(mysql::Mysql::new(direction))),
+        }
+    };
+    (
+        StreamDecoder::new(decoder(Direction::Inbound), whole_lines),
+        StreamDecoder::new(decoder(Direction::Outbound), whole_lines),
+    )
+}
+
+impl StreamDecoder {
+    fn new(decoder: Option<Box<dyn Decoder>>, whole_lines: bool) -> StreamDecoder {
+        StreamDecoder {
+            decoder,
+            buffer: Vec::new(),
+            skip: 0,
+            whole_lines,
+            partial_line: Vec::new(),
+        }
+    }
+
+    pub fn feed(&mut self, buf: &[u8]) -> Vec<Decoded> {
+        let decoder = match self.decoder.as_mut() {
+            Some(decoder) => decoder,
+            None => return self.raw(buf.to_vec()).into_iter().collect(),
+        };
+        let skipped = self.skip.min(buf.len());
+        self.skip -= skipped;
+        self.buffer.extend_from_slice(&buf[skipped..]);
+
+        let mut decoded = Vec::new();
+        let mut consumed = 0;
+        let error = loop {
+            match decoder.parse(&self.buffer[consumed..]) {
+                Ok(Some(message)) => {
+                    if !message.lines.is_empty() {
+                        decoded.push(Decoded::Lines(message.lines));
+                    }
+                    let buffered = self.buffer.len() - consumed;
+                    if message.len > buffered {
+                        self.skip = message.len - buffered;
+                        consumed = self.buffer.len();
+                        break None;
+                    }
+                    consumed += message.len;
+                }
+                Ok(None) if self.buffer.len() - consumed > MAX_BUFFERED => {
+                    break Some(format!("no message within {} bytes", MAX_BUFFERED))
+                }
+                Ok(None) => break None,
+                Err(e) => break Some(e),
+            }
+        };
+        if let Some(e) = error {
+            decoded.push(Decoded::Lines(vec![format!(
+                "decode error: {}, printing raw from here",
+                e
+            )]));
+            let rest = self.buffer.split_off(consumed);
+            self.buffer.clear();
+            self.decoder = None;
+            decoded.extend(self.raw(rest));
+            return decoded;
+        }
+        self.buffer.drain(..consumed);
+        decoded
+    }
+
+    /// Raw output up to the last newline with `whole_lines`.
+    fn raw(&mut self, buf: Vec<u8>) -> Option<Decoded> {
+        if !self.whole_lines {
+            return Some(Decoded::Raw(buf));
+        }
+        self.partial_line.extend_from_slice(&buf);
+        let end = match self.partial_line.iter().rposition(|&b| b == b'\n') {
+            Some(newline) => newline + 1,
+            None if self.partial_line.len() > MAX_PARTIAL_LINE => self.partial_line.len(),
+            None => return None,
+        };
+        let rest = self.partial_line.split_off(end);
+        Some(Decoded::Raw(std::mem::replace(
+            &mut self.partial_line,
+            rest,
+        )))
+    }
+
+    /// Bytes of the fed chunks not returned yet by `raw`.
+    pub fn held(&self) -> usize {
+        self.partial_line.len()
+    }
+
+    /// The partial line held back, once the stream ended.
+    pub fn finish(&mut self) -> Option<Decoded> {
+        if self.partial_line.is_empty() {
+            return None;
+        }
+        Some(Decoded::Raw(std::mem::take(&mut self.partial_line)))
+    }
+}
+
+/// Text for printable bytes, a byte count otherwise.
+fn describe(buf: &[u8]) -> String {
+    if is_printable(buf) {
+        format!("{:?}", String::from_utf8_lossy(buf))
+    } else {
+        format!("<{} bytes>", buf.len())
+    }
+}
+
+/// NUL terminated string at the start of `buf`, and the rest after the NUL.
+fn cstr(buf: &[u8]) -> Result<(String, &[u8]), String> {
+    let end = buf
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or("missing string terminator")?;
+    Ok((
+        String::from_utf8_lossy(&buf[..end]).into_owned(),
+        &buf[end + 1..],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reassemble_and_fallback() {
+        let (mut decoder, _) = pair(Protocol::Redis, false);
+        assert!(decoder.feed(b"*1\r\n$4\r\nPI").is_empty());
+        match decoder.feed(b"NG\r\n").as_slice() {
+            [Decoded::Lines(lines)] => assert_eq!(lines, &["PING"]),
+            _ => panic!("expected one message"),
+        }
+        match decoder.feed(b"*x\r\n").as_slice() {
+            [Decoded::Lines(_), Decoded::Raw(raw)] => assert_eq!(raw, b"*x\r\n"),
+            _ => panic!("expected fallback"),
+        }
+        assert!(matches!(
+            decoder.feed(b"PING").as_slice(),
+            [Decoded::Raw(_)]
+        ));
+    }
+
+    #[test]
+    fn whole_lines() {
+        let (mut decoder, _) = pair(Protocol::Raw, true);
+        assert!(decoder.feed(b"Authorization: Bea").is_empty());
+        assert_eq!(decoder.held(), 18);
+        match decoder.feed(b"rer token\r\nHost").as_slice() {
+            [Decoded::Raw(raw)] => assert_eq!(raw, b"Authorization: Bearer token\r\n"),
+            _ => panic!("expected the completed line"),
+        }
+        assert!(matches!(decoder.finish(), Some(Decoded::Raw(raw)) if raw == b"Host"));
+        assert!(decoder.finish().is_none());
+    }
+}
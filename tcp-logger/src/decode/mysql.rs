@@ -0,0 +1,170 @@
+use super::{cstr, describe, Decoder, Message, MAX_BUFFERED};
+use crate::capture::Direction;
+
+const CLIENT_SSL: u32 = 0x0800;
+
+/// Packets are `len (3 bytes LE) | sequence id | payload`. Commands and
+/// OK / ERR / EOF are decoded; result set rows only show their size.
+pub struct Mysql {
+    direction: Direction,
+    /// the first packet of each side is the handshake
+    handshake: bool,
+    /// the client asked for TLS, the rest is ciphertext
+    encrypted: bool,
+}
+
+impl Mysql {
+    pub fn new(direction: Direction) -> Mysql {
+        Mysql {
+            direction,
+            handshake: true,
+            encrypted: false,
+        }
+    }
+}
+
+fn client(payload: &[u8], handshake: bool) -> Result<String, String> {
+    if handshake {
+        let capabilities = payload
+            .get(..4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or("handshake response too short")?;
+        if capabilities & CLIENT_SSL != 0 && payload.len() == 32 {
+            return Ok("SSLRequest".to_string());
+        }
+        let (user, _) = cstr(payload.get(32..).ok_or("handshake response too short")?)?;
+        return Ok(format!("HandshakeResponse user={:?}", user));
+    }
+    let (&command, body) = payload.split_first().ok_or("empty packet")?;
+    Ok(match command {
+        0x01 => "COM_QUIT".to_string(),
+        0x02 => format!("COM_INIT_DB {}", describe(body)),
+        0x03 => format!("COM_QUERY {}", describe(body)),
+        0x0e => "COM_PING".to_string(),
+        0x11 => "COM_CHANGE_USER".to_string(),
+        0x16 => format!("COM_STMT_PREPARE {}", describe(body)),
+        0x17 => format!("COM_STMT_EXECUTE <{} bytes>", body.len()),
+        0x19 => "COM_STMT_CLOSE".to_string(),
+        0x1a => "COM_STMT_RESET".to_string(),
+        0x1f => "COM_RESET_CONNECTION".to_string(),
+        _ => format!("command 0x{:02x} <{} bytes>", command, body.len()),
+    })
+}
+
+fn server(payload: &[u8], handshake: bool) -> Result<String, String> {
+    if handshake {
+        let (&version, rest) = payload.split_first().ok_or("empty packet")?;
+        if version != 10 {
+            return Err(format!("unsupported handshake version {}", version));
+        }
+        return Ok(format!("Handshake server={:?}", cstr(rest)?.0));
+    }
+    Ok(match payload.first() {
+        Some(0x00) if payload.len() >= 7 => "OK".to_string(),
+        Some(0xff) => {
+            let code = payload
+                .get(1..3)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .ok_or("error packet too short")?;
+            let message = match payload.get(3) {
+                Some(b'#') => payload.get(9..).unwrap_or_default(),
+                _ => &payload[3..],
+            };
+            format!("ERR {} {}", code, String::from_utf8_lossy(message))
+        }
+        Some(0xfe) if payload.len() < 9 => "EOF".to_string(),
+        _ => format!("<{} bytes>", payload.len()),
+    })
+}
+
+impl Decoder for Mysql {
+    fn parse(&mut self, buf: &[u8]) -> Result<Option<Message>, String> {
+        if self.encrypted {
+            return Err("connection switched to TLS".to_string());
+        }
+        if buf.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes([buf[0], buf[1], buf[2], 0]) as usize;
+        let sequence = buf[3];
+        if 4 + len > MAX_BUFFERED {
+            // not buffered whole, the rest is skipped as it arrives
+            self.handshake = false;
+            return Ok(Some(Message {
+                len: 4 + len,
+                lines: vec![format!("#{} <{} bytes>", sequence, len)],
+            }));
+        }
+        if buf.len() < 4 + len {
+            return Ok(None);
+        }
+        let payload = &buf[4..4 + len];
+        let line = match self.direction {
+            Direction::Inbound => client(payload, self.handshake)?,
+            Direction::Outbound => server(payload, self.handshake)?,
+        };
+        self.encrypted = self.handshake && line == "SSLRequest";
+        self.handshake = false;
+        Ok(Some(Message {
+            len: 4 + len,
+            lines: vec![format!("#{} {}", sequence, line)],
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buf = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+        buf.push(sequence);
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn packets() {
+        let mut server = Mysql::new(Direction::Outbound);
+        assert_eq!(
+            server
+                .parse(&packet(0, b"\x0a8.0.36\0rest"))
+                .unwrap()
+                .unwrap()
+                .lines,
+            vec!["#0 Handshake server=\"8.0.36\""]
+        );
+        assert_eq!(
+            server
+                .parse(&packet(1, b"\xff\x7a\x04#42S02Table 'x' doesn't exist"))
+                .unwrap()
+                .unwrap()
+                .lines,
+            vec!["#1 ERR 1146 Table 'x' doesn't exist"]
+        );
+
+        let mut client = Mysql::new(Direction::Inbound);
+        let mut response = vec![0; 32];
+        response.extend_from_slice(b"root\0");
+        client.parse(&packet(1, &response)).unwrap().unwrap();
+        assert_eq!(
+            client
+                .parse(&packet(0, b"\x03select 1"))
+                .unwrap()
+                .unwrap()
+                .lines,
+            vec!["#0 COM_QUERY \"select 1\""]
+        );
+        assert_eq!(
+            client
+                .parse(&packet(0, b"\x03sel")[..5])
+                .unwrap()
+                .map(|m| m.len),
+            None
+        );
+        let large = packet(2, &vec![0; MAX_BUFFERED]);
+        let summary = client.parse(&large[..4]).unwrap().unwrap();
+        assert_eq!(summary.len, MAX_BUFFERED + 4);
+        assert_eq!(summary.lines, vec![format!("#2 <{} bytes>", MAX_BUFFERED)]);
+    }
+}
@@ -0,0 +1,313 @@
+use super::{cstr, describe, Decoder, Message, MAX_BUFFERED};
+use crate::capture::Direction;
+
+/// Larger messages are taken as a sign that the stream is not PostgreSQL.
+const MAX_MESSAGE: usize = 64 * 1024 * 1024;
+
+const SSL_REQUEST: u32 = 80877103;
+const GSSENC_REQUEST: u32 = 80877104;
+const CANCEL_REQUEST: u32 = 80877102;
+
+pub struct Postgres {
+    direction: Direction,
+    /// frontend: the next message is an untyped startup packet.
+    /// backend: the next message may be the single byte SSL / GSS answer.
+    startup: bool,
+}
+
+impl Postgres {
+    pub fn new(direction: Direction) -> Postgres {
+        Postgres {
+            direction,
+            startup: true,
+        }
+    }
+}
+
+fn u16_at(buf: &[u8], at: usize) -> Result<u16, String> {
+    buf.get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| "message too short".to_string())
+}
+
+fn i32_at(buf: &[u8], at: usize) -> Result<i32, String> {
+    buf.get(at..at + 4)
+        .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| "message too short".to_string())
+}
+
+fn startup(body: &[u8]) -> Result<(String, bool), String> {
+    let code = i32_at(body, 0)? as u32;
+    Ok(match code {
+        SSL_REQUEST => ("SSLRequest".to_string(), true),
+        GSSENC_REQUEST => ("GSSENCRequest".to_string(), true),
+        CANCEL_REQUEST => ("CancelRequest".to_string(), false),
+        _ => {
+            let mut params = Vec::new();
+            let mut rest = &body[4..];
+            while rest.first().is_some_and(|&b| b != 0) {
+                let (name, r) = cstr(rest)?;
+                let (value, r) = cstr(r)?;
+                params.push(format!("{}={}", name, value));
+                rest = r;
+            }
+            (
+                format!(
+                    "StartupMessage version={}.{} {}",
+                    code >> 16,
+                    code & 0xffff,
+                    params.join(" ")
+                ),
+                false,
+            )
+        }
+    })
+}
+
+fn frontend(kind: u8, body: &[u8]) -> Result<String, String> {
+    Ok(match kind {
+        b'Q' => format!("Query {:?}", cstr(body)?.0),
+        b'P' => {
+            let (name, rest) = cstr(body)?;
+            let (query, _) = cstr(rest)?;
+            format!("Parse name={:?} {:?}", name, query)
+        }
+        b'B' => {
+            let (portal, rest) = cstr(body)?;
+            let (statement, _) = cstr(rest)?;
+            format!("Bind portal={:?} statement={:?}", portal, statement)
+        }
+        b'E' => format!("Execute portal={:?}", cstr(body)?.0),
+        b'D' => format!("Describe {}", describe(body)),
+        b'C' => format!("Close {}", describe(body)),
+        b'S' => "Sync".to_string(),
+        b'H' => "Flush".to_string(),
+        b'X' => "Terminate".to_string(),
+        b'p' => format!("PasswordMessage <{} bytes>", body.len()),
+        b'd' => format!("CopyData <{} bytes>", body.len()),
+        b'c' => "CopyDone".to_string(),
+        b'f' => format!("CopyFail {:?}", cstr(body)?.0),
+        b'F' => format!("FunctionCall <{} bytes>", body.len()),
+        _ => return Err(format!("unknown frontend message 0x{:02x}", kind)),
+    })
+}
+
+/// `S` / `C` / `M` ... fields of ErrorResponse and NoticeResponse.
+fn fields(body: &[u8]) -> Result<String, String> {
+    let mut fields = Vec::new();
+    let mut rest = body;
+    while let Some((&code, r)) = rest.split_first() {
+        if code == 0 {
+            break;
+        }
+        let (value, r) = cstr(r)?;
+        fields.push(format!("{}={}", code as char, value));
+        rest = r;
+    }
+    Ok(fields.join(" "))
+}
+
+fn backend(kind: u8, body: &[u8]) -> Result<String, String> {
+    Ok(match kind {
+        b'R' => match i32_at(body, 0)? {
+            0 => "AuthenticationOk".to_string(),
+            3 => "AuthenticationCleartextPassword".to_string(),
+            5 => "AuthenticationMD5Password".to_string(),
+            10 => "AuthenticationSASL".to_string(),
+            11 => "AuthenticationSASLContinue".to_string(),
+            12 => "AuthenticationSASLFinal".to_string(),
+            code => format!("Authentication code={}", code),
+        },
+        b'S' => {
+            let (name, rest) = cstr(body)?;
+            let (value, _) = cstr(rest)?;
+            format!("ParameterStatus {}={}", name, value)
+        }
+        b'K' => format!("BackendKeyData pid={}", i32_at(body, 0)?),
+        b'Z' => format!(
+            "ReadyForQuery {}",
+            body.first().map(|&b| b as char).unwrap_or('?')
+        ),
+        b'T' => {
+            let count = u16_at(body, 0)?;
+            let mut columns = Vec::new();
+            let mut rest = &body[2..];
+            for _ in 0..count {
+                let (name, r) = cstr(rest)?;
+                let type_oid = i32_at(r, 6)?;
+                columns.push(format!("{}(oid {})", name, type_oid));
+                rest = r.get(18..).ok_or("message too short")?;
+            }
+            format!("RowDescription {}", columns.join(", "))
+        }
+        b'D' => {
+            let count = u16_at(body, 0)?;
+            let mut values = Vec::new();
+            let mut at = 2;
+            for _ in 0..count {
+                let len = i32_at(body, at)?;
+                at += 4;
+                if len < 0 {
+                    values.push("NULL".to_string());
+                    continue;
+                }
+                let value = body.get(at..at + len as usize).ok_or("message too short")?;
+                values.push(describe(value));
+                at += len as usize;
+            }
+            format!("DataRow {}", values.join(", "))
+        }
+        b'C' => format!("CommandComplete {:?}", cstr(body)?.0),
+        b'E' => format!("ErrorResponse {}", fields(body)?),
+        b'N' => format!("NoticeResponse {}", fields(body)?),
+        b'1' => "ParseComplete".to_string(),
+        b'2' => "BindComplete".to_string(),
+        b'3' => "CloseComplete".to_string(),
+        b'n' => "NoData".to_string(),
+        b's' => "PortalSuspended".to_string(),
+        b'I' => "EmptyQueryResponse".to_string(),
+        b't' => format!("ParameterDescription count={}", u16_at(body, 0)?),
+        b'A' => format!("NotificationResponse pid={}", i32_at(body, 0)?),
+        b'G' => "CopyInResponse".to_string(),
+        b'H' => "CopyOutResponse".to_string(),
+        b'W' => "CopyBothResponse".to_string(),
+        b'd' => format!("CopyData <{} bytes>", body.len()),
+        b'c' => "CopyDone".to_string(),
+        b'v' => "NegotiateProtocolVersion".to_string(),
+        _ => return Err(format!("unknown backend message 0x{:02x}", kind)),
+    })
+}
+
+impl Decoder for Postgres {
+    fn parse(&mut self, buf: &[u8]) -> Result<Option<Message>, String> {
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        if self.startup && self.direction == Direction::Outbound {
+            self.startup = false;
+            if matches!(buf[0], b'S' | b'N' | b'G') {
+                let answer = match buf[0] {
+                    b'S' => "SSL accepted",
+                    b'G' => "GSS encryption accepted",
+                    _ => "SSL / GSS encryption rejected",
+                };
+                return Ok(Some(Message {
+                    len: 1,
+                    lines: vec![answer.to_string()],
+                }));
+            }
+        }
+
+        let typed = !(self.startup && self.direction == Direction::Inbound);
+        let header = if typed { 5 } else { 4 };
+        if buf.len() < header {
+            return Ok(None);
+        }
+        let len = i32_at(buf, header - 4)?;
+        if len < 4 || len as usize > MAX_MESSAGE {
+            return Err(format!("invalid message length {}", len));
+        }
+        let total = header - 4 + len as usize;
+        if typed && total > MAX_BUFFERED {
+            // not buffered whole, the rest is skipped as it arrives
+            return Ok(Some(Message {
+                len: total,
+                lines: vec![format!("message {:?} <{} bytes>", buf[0] as char, len - 4)],
+            }));
+        }
+        if buf.len() < total {
+            return Ok(None);
+        }
+        let body = &buf[header..total];
+
+        let line = if typed {
+            match self.direction {
+                Direction::Inbound => frontend(buf[0], body)?,
+                Direction::Outbound => backend(buf[0], body)?,
+            }
+        } else {
+            let (line, again) = startup(body)?;
+            self.startup = again;
+            line
+        };
+        Ok(Some(Message {
+            len: total,
+            lines: vec![line],
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(kind: u8, body: &[u8]) -> Vec<u8> {
+        let mut buf = vec![kind];
+        buf.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        buf.extend_from_slice(body);
+        buf
+    }
+
+    #[test]
+    fn frontend_and_backend() {
+        let mut frontend = Postgres::new(Direction::Inbound);
+        let mut startup = 8i32.to_be_bytes().to_vec();
+        startup.extend_from_slice(&SSL_REQUEST.to_be_bytes());
+        assert_eq!(
+            frontend.parse(&startup).unwrap().unwrap().lines,
+            vec!["SSLRequest"]
+        );
+        let mut startup = 17i32.to_be_bytes().to_vec();
+        startup.extend_from_slice(&196608i32.to_be_bytes());
+        startup.extend_from_slice(b"user\0pg\0\0");
+        assert_eq!(
+            frontend.parse(&startup).unwrap().unwrap().lines,
+            vec!["StartupMessage version=3.0 user=pg"]
+        );
+        assert_eq!(
+            frontend
+                .parse(&message(b'Q', b"select 1\0"))
+                .unwrap()
+                .unwrap()
+                .lines,
+            vec!["Query \"select 1\""]
+        );
+
+        let mut backend = Postgres::new(Direction::Outbound);
+        assert_eq!(backend.parse(b"N").unwrap().unwrap().len, 1);
+        let mut row = vec![0, 1];
+        row.extend_from_slice(b"n\0");
+        row.extend_from_slice(&[0; 6]);
+        row.extend_from_slice(&23i32.to_be_bytes());
+        row.extend_from_slice(&[0; 8]);
+        assert_eq!(
+            backend.parse(&message(b'T', &row)).unwrap().unwrap().lines,
+            vec!["RowDescription n(oid 23)"]
+        );
+        let mut data = vec![0, 2];
+        data.extend_from_slice(&1i32.to_be_bytes());
+        data.push(b'1');
+        data.extend_from_slice(&(-1i32).to_be_bytes());
+        assert_eq!(
+            backend.parse(&message(b'D', &data)).unwrap().unwrap().lines,
+            vec!["DataRow \"1\", NULL"]
+        );
+        assert_eq!(
+            backend
+                .parse(&message(b'E', b"SERROR\0C42P01\0\0"))
+                .unwrap()
+                .unwrap()
+                .lines,
+            vec!["ErrorResponse S=ERROR C=42P01"]
+        );
+        assert!(backend.parse(&message(b'?', b"")).is_err());
+        let mut large = vec![b'D'];
+        large.extend_from_slice(&(MAX_BUFFERED as i32 + 4).to_be_bytes());
+        let summary = backend.parse(&large).unwrap().unwrap();
+        assert_eq!(summary.len, MAX_BUFFERED + 5);
+        assert_eq!(
+            summary.lines,
+            vec![format!("message 'D' <{} bytes>", MAX_BUFFERED)]
+        );
+    }
+}
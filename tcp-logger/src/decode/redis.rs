@@ -0,0 +1,334 @@
+use super::{describe, Decoder, Message, MAX_BUFFERED};
+
+/// RESP2 / RESP3 values; inline commands (`PING\r\n`) are accepted as well.
+///
+/// Elements of an aggregate are consumed as they arrive, so a long pipelined
+/// reply is parsed once instead of again with every chunk.
+#[derive(Default)]
+pub struct Redis {
+    /// aggregates of the current message still waiting for elements,
+    /// outermost first
+    open: Vec<Aggregate>,
+}
+
+struct Aggregate {
+    kind: u8,
+    remaining: usize,
+    values: Vec<Value>,
+}
+
+impl Aggregate {
+    fn finish(self) -> Value {
+        if self.kind == b'%' {
+            let mut pairs = Vec::new();
+            let mut values = self.values.into_iter();
+            while let (Some(k), Some(v)) = (values.next(), values.next()) {
+                pairs.push((k, v));
+            }
+            Value::Map(pairs)
+        } else {
+            Value::Array(self.values)
+        }
+    }
+}
+
+enum Value {
+    Simple(String),
+    Error(String),
+    Integer(String),
+    Bulk(Vec<u8>),
+    /// bulk string too large to buffer, skipped
+    Skipped(usize),
+    Null,
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+    fn render(&self) -> String {
+        match self {
+            Value::Simple(s) => s.clone(),
+            Value::Error(s) => format!("(error) {}", s),
+            Value::Integer(s) => format!("(integer) {}", s),
+            Value::Bulk(b) => describe(b),
+            Value::Skipped(len) => format!("<{} bytes>", len),
+            Value::Null => "(nil)".to_string(),
+            Value::Array(values) => format!(
+                "[{}]",
+                values
+                    .iter()
+                    .map(Value::render)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Value::Map(pairs) => format!(
+                "{{{}}}",
+                pairs
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k.render(), v.render()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// Commands are arrays of bulk strings; print them like redis-cli input.
+    fn render_command(&self) -> Option<String> {
+        let Value::Array(values) = self else {
+            return None;
+        };
+        if values.is_empty() {
+            return None;
+        }
+        let mut words = Vec::new();
+        for value in values {
+            let b = match value {
+                Value::Bulk(b) => b,
+                Value::Skipped(_) => {
+                    words.push(value.render());
+                    continue;
+                }
+                _ => return None,
+            };
+            match std::str::from_utf8(b) {
+                Ok(s)
+                    if !s.is_empty()
+                        && !s.contains(|c: char| {
+                            c.is_whitespace() || c.is_control() || c == '"'
+                        }) =>
+                {
+                    words.push(s.to_string())
+                }
+                _ => words.push(describe(b)),
+            }
+        }
+        Some(words.join(" "))
+    }
+}
+
+/// Line up to `\r\n` and the bytes after it.
+fn line(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = buf.windows(2).position(|w| w == b"\r\n")?;
+    Some((&buf[..end], &buf[end + 2..]))
+}
+
+fn number(buf: &[u8]) -> Result<i64, String> {
+    std::str::from_utf8(buf)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| format!("invalid length {:?}", String::from_utf8_lossy(buf)))
+}
+
+/// Deepest nesting of aggregates accepted.
+const MAX_DEPTH: usize = 32;
+
+/// Redis' own limit (`proto-max-bulk-len`); longer bulk strings are taken as
+/// a sign that the stream is not RESP.
+const MAX_BULK: i64 = 512 * 1024 * 1024;
+
+enum Item {
+    Value(Value),
+    /// header of an aggregate, its elements follow
+    Open(Aggregate),
+}
+
+/// Parses the item at the start of `buf` and returns it with the bytes it
+/// takes, which can be more than buffered for a skipped bulk string.
+/// `depth` is the number of aggregates around it.
+fn item(buf: &[u8], depth: usize) -> Result<Option<(Item, usize)>, String> {
+    let Some((head, rest)) = line(buf) else {
+        return Ok(None);
+    };
+    let start = buf.len() - rest.len();
+    let (kind, text) = match head.split_first() {
+        Some((kind, text)) => (*kind, text),
+        None => return Err("empty line".to_string()),
+    };
+    let text_str = || String::from_utf8_lossy(text).into_owned();
+    let value = match kind {
+        b'+' => Value::Simple(text_str()),
+        b'-' | b'!' => Value::Error(text_str()),
+        b':' | b',' | b'(' | b'#' => Value::Integer(text_str()),
+        b'_' => Value::Null,
+        b'$' | b'=' => {
+            let len = number(text)?;
+            if len < 0 {
+                return Ok(Some((Item::Value(Value::Null), start)));
+            }
+            if len > MAX_BULK {
+                return Err(format!("invalid bulk length {}", len));
+            }
+            let len = len as usize;
+            let end = start + len + 2;
+            if end > MAX_BUFFERED {
+                // not buffered whole, the rest is skipped as it arrives
+                return Ok(Some((Item::Value(Value::Skipped(len)), end)));
+            }
+            if buf.len() < end {
+                return Ok(None);
+            }
+            if &rest[len..len + 2] != b"\r\n" {
+                return Err("bulk string not terminated".to_string());
+            }
+            return Ok(Some((Item::Value(Value::Bulk(rest[..len].to_vec())), end)));
+        }
+        b'*' | b'~' | b'>' | b'%' => {
+            let n = number(text)?;
+            if n < 0 {
+                return Ok(Some((Item::Value(Value::Null), start)));
+            }
+            if depth == MAX_DEPTH {
+                return Err(format!("nested deeper than {} levels", MAX_DEPTH));
+            }
+            let remaining = match kind {
+                b'%' => n
+                    .checked_mul(2)
+                    .ok_or_else(|| format!("invalid map size {}", n))?,
+                _ => n,
+            };
+            let aggregate = Aggregate {
+                kind,
+                remaining: remaining as usize,
+                values: Vec::new(),
+            };
+            return Ok(Some((Item::Open(aggregate), start)));
+        }
+        _ => return Err(format!("unknown type byte 0x{:02x}", kind)),
+    };
+    Ok(Some((Item::Value(value), start)))
+}
+
+impl Decoder for Redis {
+    /// Hands over complete elements of an unfinished message as a message
+    /// without lines; the message is printed once its last element arrived.
+    fn parse(&mut self, buf: &[u8]) -> Result<Option<Message>, String> {
+        let Some(&first) = buf.first() else {
+            return Ok(None);
+        };
+        if self.open.is_empty() && first.is_ascii_alphabetic() {
+            let Some((head, rest)) = line(buf) else {
+                return Ok(None);
+            };
+            return Ok(Some(Message {
+                len: buf.len() - rest.len(),
+                lines: vec![String::from_utf8_lossy(head).into_owned()],
+            }));
+        }
+        let mut pos = 0;
+        loop {
+            let Some((item, len)) = item(&buf[pos..], self.open.len())? else {
+                return Ok((pos > 0).then(|| Message {
+                    len: pos,
+                    lines: Vec::new(),
+                }));
+            };
+            pos += len;
+            let mut value = match item {
+                Item::Open(aggregate) if aggregate.remaining > 0 => {
+                    self.open.push(aggregate);
+                    continue;
+                }
+                Item::Open(aggregate) => aggregate.finish(),
+                Item::Value(value) => value,
+            };
+            // the value may complete the aggregates around it
+            loop {
+                let Some(aggregate) = self.open.last_mut() else {
+                    return Ok(Some(Message {
+                        len: pos,
+                        lines: vec![value.render_command().unwrap_or_else(|| value.render())],
+                    }));
+                };
+                aggregate.values.push(value);
+                aggregate.remaining -= 1;
+                if aggregate.remaining > 0 {
+                    break;
+                }
+                value = self.open.pop().unwrap().finish();
+            }
+            if pos > buf.len() {
+                // a skipped bulk string ends past the buffer
+                return Ok(Some(Message {
+                    len: pos,
+                    lines: Vec::new(),
+                }));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::Decoded;
+
+    #[test]
+    fn resp() {
+        let parse = |buf: &[u8]| {
+            Redis::default()
+                .parse(buf)
+                .unwrap()
+                .map(|m| (m.len, m.lines))
+        };
+        assert_eq!(
+            parse(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\na b c\r\n+OK"),
+            Some((33, vec!["SET key \"a b c\"".to_string()]))
+        );
+        assert_eq!(parse(b"+OK\r\n"), Some((5, vec!["OK".to_string()])));
+        assert_eq!(
+            parse(b"*2\r\n:1\r\n$-1\r\n"),
+            Some((13, vec!["[(integer) 1, (nil)]".to_string()]))
+        );
+        assert_eq!(
+            parse(b"-ERR unknown\r\n"),
+            Some((14, vec!["(error) ERR unknown".to_string()]))
+        );
+        assert_eq!(parse(b"PING\r\n"), Some((6, vec!["PING".to_string()])));
+        assert_eq!(parse(b"*0\r\n"), Some((4, vec!["[]".to_string()])));
+        assert_eq!(parse(b"$5\r\nhel"), None);
+        assert!(Redis::default().parse(b"?\r\n").is_err());
+        assert!(Redis::default()
+            .parse(&b"*1\r\n".repeat(MAX_DEPTH + 1))
+            .is_err());
+        assert_eq!(
+            parse(&b"*1\r\n".repeat(MAX_DEPTH)),
+            Some((4 * MAX_DEPTH, vec![]))
+        );
+        assert!(Redis::default().parse(b"%9223372036854775807\r\n").is_err());
+        assert!(Redis::default().parse(b"$9223372036854775807\r\n").is_err());
+    }
+
+    #[test]
+    fn resumes_aggregates() {
+        let mut redis = Redis::default();
+        let first = redis.parse(b"*3\r\n:1\r\n:2").unwrap().unwrap();
+        assert_eq!((first.len, first.lines.len()), (8, 0));
+        assert!(redis.parse(b":2").unwrap().is_none());
+        let last = redis.parse(b":2\r\n:3\r\n").unwrap().unwrap();
+        assert_eq!(last.len, 8);
+        assert_eq!(last.lines, vec!["[(integer) 1, (integer) 2, (integer) 3]"]);
+        assert!(redis.open.is_empty());
+    }
+
+    #[test]
+    fn skips_large_bulk_strings() {
+        let len = MAX_BUFFERED;
+        let set = format!("*3\r\n$3\r\nSET\r\n$1\r\nk\r\n${}\r\n", len);
+        let message = Redis::default().parse(set.as_bytes()).unwrap().unwrap();
+        assert_eq!(message.len, set.len() + len + 2);
+        assert_eq!(message.lines, vec![format!("SET k <{} bytes>", len)]);
+
+        // elements after the skipped bulk string are parsed once it passed
+        let (mut decoder, _) = crate::decode::pair(crate::decode::Protocol::Redis, false);
+        assert!(decoder
+            .feed(format!("*2\r\n${}\r\n", len).as_bytes())
+            .is_empty());
+        for _ in 0..len / 4096 {
+            assert!(decoder.feed(&[b'x'; 4096]).is_empty());
+        }
+        match decoder.feed(b"\r\n+OK\r\n").as_slice() {
+            [Decoded::Lines(lines)] => assert_eq!(lines, &[format!("[<{} bytes>, OK]", len)]),
+            _ => panic!("expected the array"),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::capture::Direction;
+use crate::stream::Socket;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+/// Bad network conditions applied while copying, see `copy`.
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    /// added to each chunk, counted from when it was read
+    pub latency: Duration,
+    /// bytes per second, client -> server
+    pub bandwidth_inbound: Option<u64>,
+    /// bytes per second, server -> client
+    pub bandwidth_outbound: Option<u64>,
+    /// reset connections after this many bytes in both directions together
+    pub reset_after: Option<u64>,
+    /// share of connections that get reset
+    pub reset_probability: f64,
+    /// chance of each chunk to stall for `stall`
+    pub stall_probability: f64,
+    pub stall: Duration,
+}
+
+/// Parses `--reset-probability` / `--stall-probability`.
+pub fn parse_probability(s: &str) -> Result<f64, String> {
+    let p: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if !(0.0..=1.0).contains(&p) {
+        return Err(format!("{} is not between 0 and 1", s));
+    }
+    Ok(p)
+}
+
+impl Faults {
+    /// Decides once per connection whether it will be reset.
+    pub fn plan(&self) -> Plan {
+        let reset_at = self
+            .reset_after
+            .filter(|_| fastrand::f64() < self.reset_probability);
+        Plan {
+            reset_at,
+            forwarded: AtomicU64::new(0),
+            reset: AtomicBool::new(false),
+        }
+    }
+
+    fn bandwidth(&self, direction: Direction) -> Option<u64> {
+        match direction {
+            Direction::Inbound => self.bandwidth_inbound,
+            Direction::Outbound => self.bandwidth_outbound,
+        }
+    }
+}
+
+/// Faults of one connection, shared by both directions.
+pub struct Plan {
+    reset_at: Option<u64>,
+    forwarded: AtomicU64,
+    reset: AtomicBool,
+}
+
+impl Plan {
+    pub fn resets(&self) -> bool {
+        self.reset_at.is_some()
+    }
+
+    /// The connection was cut by an injected reset.
+    pub fn was_reset(&self) -> bool {
+        self.reset.load(Ordering::Relaxed)
+    }
+}
+
+/// Keeps a second handle on the socket of a connection that will be reset:
+/// setting `SO_LINGER` to zero on it makes the close send RST instead of FIN,
+/// without affecting connections that are closed normally. Unix domain
+/// sockets have no reset and are just closed.
+pub fn reset_handle(socket: Socket) -> io::Result<(Socket, Option<TcpStream>)> {
+    let Socket::Tcp(stream) = socket else {
+        return Ok((socket, None));
+    };
+    let stream = stream.into_std()?;
+    let handle = stream.try_clone()?;
+    Ok((
+        Socket::Tcp(TcpStream::from_std(stream)?),
+        Some(TcpStream::from_std(handle)?),
+    ))
+}
+
+/// Chunks held back by `--latency` per direction, 8 MiB with full reads.
+/// Reading pauses while the queue is full.
+const QUEUED_CHUNKS: usize = 1024;
+
+/// `io::copy` with the faults applied to every chunk read from `reader`.
+/// Latency is counted from when a chunk was read, so it delays the stream
+/// without limiting its throughput; stalls and bandwidth hold back the
+/// following chunks as well.
+pub async fn copy<R, W>(
+    faults: &Faults,
+    plan: &Plan,
+    direction: Direction,
+    reader: &mut R,
+    writer: &mut W,
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut forward = Forward {
+        faults,
+        plan,
+        direction,
+        writer,
+        started: Instant::now(),
+        copied: 0,
+    };
+    let mut buf = vec![0; 8192];
+    if faults.latency.is_zero() {
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(forward.copied);
+            }
+            forward.chunk(&buf[..n]).await?;
+        }
+    }
+
+    let (queue, mut queued) = mpsc::channel::<(Instant, Vec<u8>)>(QUEUED_CHUNKS);
+    let read = async move {
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            let due = Instant::now() + faults.latency;
+            if queue.send((due, buf[..n].to_vec())).await.is_err() {
+                return Ok(());
+            }
+        }
+    };
+    let write = async {
+        while let Some((due, chunk)) = queued.recv().await {
+            tokio::time::sleep_until(due.into()).await;
+            forward.chunk(&chunk).await?;
+        }
+        Ok::<_, io::Error>(())
+    };
+    tokio::try_join!(read, write)?;
+    Ok(forward.copied)
+}
+
+/// Writing side of `copy`.
+struct Forward<'a, W: ?Sized> {
+    faults: &'a Faults,
+    plan: &'a Plan,
+    direction: Direction,
+    writer: &'a mut W,
+    started: Instant,
+    copied: u64,
+}
+
+impl<W: AsyncWrite + Unpin + ?Sized> Forward<'_, W> {
+    async fn chunk(&mut self, mut chunk: &[u8]) -> io::Result<()> {
+        let n = chunk.len() as u64;
+        let faults = self.faults;
+        if faults.stall_probability > 0.0 && fastrand::f64() < faults.stall_probability {
+            tokio::time::sleep(faults.stall).await;
+        }
+        if let Some(rate) = faults.bandwidth(self.direction).filter(|&rate| rate > 0) {
+            // wait until the chunk fits into the rate since the start
+            let due = Duration::from_secs_f64((self.copied + n) as f64 / rate as f64);
+            tokio::time::sleep_until((self.started + due).into()).await;
+        }
+
+        let forwarded = self.plan.forwarded.fetch_add(n, Ordering::Relaxed);
+        if let Some(reset_at) = self.plan.reset_at {
+            if forwarded + n >= reset_at {
+                chunk = &chunk[..reset_at.saturating_sub(forwarded) as usize];
+                self.writer.write_all(chunk).await?;
+                self.writer.flush().await?;
+                self.plan.reset.store(true, Ordering::Relaxed);
+                return Err(io::Error::new(
+                    ErrorKind::ConnectionReset,
+                    format!("reset injected after {} bytes", reset_at),
+                ));
+            }
+        }
+
+        self.writer.write_all(chunk).await?;
+        self.copied += n;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probability() {
+        assert_eq!(parse_probability("0.2"), Ok(0.2));
+        assert_eq!(parse_probability("1"), Ok(1.0));
+        assert!(parse_probability("1.5").is_err());
+        assert!(parse_probability("-0.1").is_err());
+        assert!(parse_probability("NaN").is_err());
+    }
+
+    #[tokio::test]
+    async fn reset_after() {
+        let faults = Faults {
+            reset_after: Some(5),
+            reset_probability: 1.0,
+            ..Default::default()
+        };
+        let plan = faults.plan();
+        let mut reader: &[u8] = b"hello world";
+        let mut writer = Vec::new();
+        let result = copy(&faults, &plan, Direction::Inbound, &mut reader, &mut writer).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ConnectionReset);
+        assert_eq!(writer, b"hello");
+        assert!(plan.was_reset());
+
+        let faults = Faults::default();
+        let plan = faults.plan();
+        let mut reader: &[u8] = b"hello world";
+        let mut writer = Vec::new();
+        let copied = copy(&faults, &plan, Direction::Inbound, &mut reader, &mut writer).await;
+        assert_eq!(copied.unwrap(), 11);
+        assert!(!plan.resets());
+    }
+
+    #[tokio::test]
+    async fn latency_is_not_per_chunk() {
+        let faults = Faults {
+            latency: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let plan = faults.plan();
+        let (mut client, mut reader) = io::duplex(64);
+        let mut writer = Vec::new();
+        let started = Instant::now();
+        let send = async {
+            for _ in 0..10 {
+                client.write_all(b"chunk").await.unwrap();
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            drop(client);
+        };
+        let (_, copied) = tokio::join!(
+            send,
+            copy(&faults, &plan, Direction::Inbound, &mut reader, &mut writer)
+        );
+        assert_eq!(copied.unwrap(), 50);
+        assert_eq!(writer, b"chunk".repeat(10));
+        // reading takes ~50ms, one delay per chunk would add 500ms
+        assert!(started.elapsed() < Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,116 @@
+use regex::bytes::Regex;
+use std::borrow::Cow;
+
+/// Decides which chunks are printed and hides sensitive parts of them. The
+/// capture is not affected.
+#[derive(Default)]
+pub struct Filter {
+    pub redact: Vec<Regex>,
+    pub include: Vec<Regex>,
+    pub exclude: Vec<Regex>,
+}
+
+const REDACTED: &[u8] = b"[REDACTED]";
+
+impl Filter {
+    /// Replaces the capture groups of every match, or the whole match when
+    /// the pattern has none.
+    pub fn redact<'a>(&self, buf: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut buf = Cow::Borrowed(buf);
+        for regex in &self.redact {
+            let mut ranges = Vec::new();
+            for captures in regex.captures_iter(&buf) {
+                let groups: Vec<_> = captures.iter().skip(1).flatten().collect();
+                if groups.is_empty() && regex.captures_len() == 1 {
+                    ranges.extend(captures.get(0).map(|m| m.range()));
+                } else {
+                    ranges.extend(groups.iter().map(|m| m.range()));
+                }
+            }
+            if ranges.is_empty() {
+                continue;
+            }
+            let mut redacted = Vec::with_capacity(buf.len());
+            let mut last = 0;
+            for range in ranges {
+                if range.start < last {
+                    continue;
+                }
+                redacted.extend_from_slice(&buf[last..range.start]);
+                redacted.extend_from_slice(REDACTED);
+                last = range.end;
+            }
+            redacted.extend_from_slice(&buf[last..]);
+            buf = Cow::Owned(redacted);
+        }
+        buf
+    }
+
+    pub fn redact_line(&self, line: String) -> String {
+        match self.redact(line.as_bytes()) {
+            Cow::Borrowed(_) => line,
+            Cow::Owned(redacted) => String::from_utf8_lossy(&redacted).into_owned(),
+        }
+    }
+
+    /// Patterns are matched against the chunk and the lines printed for it.
+    pub fn shows(&self, buf: &[u8], lines: &[String]) -> bool {
+        let matches = |regex: &Regex| {
+            regex.is_match(buf) || lines.iter().any(|line| regex.is_match(line.as_bytes()))
+        };
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_and_filter() {
+        let filter = Filter {
+            redact: vec![
+                Regex::new(r"(?m)^Authorization: .*$").unwrap(),
+                Regex::new(r"password=(\S+)").unwrap(),
+            ],
+            include: vec![Regex::new("GET|POST").unwrap()],
+            exclude: vec![Regex::new("/health").unwrap()],
+        };
+        assert_eq!(
+            filter.redact(b"POST /login?password=hunter2 HTTP/1.1\r\nAuthorization: Bearer x"),
+            &b"POST /login?password=[REDACTED] HTTP/1.1\r\n[REDACTED]"[..]
+        );
+        assert!(matches!(filter.redact(b"nothing"), Cow::Borrowed(_)));
+        assert!(filter.shows(b"GET / HTTP/1.1", &[]));
+        assert!(!filter.shows(b"GET /health HTTP/1.1", &[]));
+        assert!(!filter.shows(b"HTTP/1.1 200 OK", &[]));
+        assert!(filter.shows(b"\x03", &["COM_QUERY \"POST\"".to_string()]));
+    }
+
+    #[test]
+    fn redact_across_chunks() {
+        use crate::decode::{pair, Decoded, Protocol};
+
+        let filter = Filter {
+            redact: vec![Regex::new(r"Authorization: Bearer (\S+)").unwrap()],
+            ..Default::default()
+        };
+        let (mut decoder, _) = pair(Protocol::Raw, true);
+        let mut printed = Vec::new();
+        for chunk in [
+            &b"GET / HTTP/1.1\r\nAuthorization: Bea"[..],
+            b"rer secret\r\n\r\n",
+        ] {
+            for decoded in decoder.feed(chunk) {
+                if let Decoded::Raw(raw) = decoded {
+                    printed.extend_from_slice(&filter.redact(&raw));
+                }
+            }
+        }
+        assert_eq!(
+            printed,
+            b"GET / HTTP/1.1\r\nAuthorization: Bearer [REDACTED]\r\n\r\n"
+        );
+    }
+}
@@ -1,107 +1,687 @@
+mod capture;
+mod decode;
+mod fault;
+mod filter;
+mod mapping;
+mod proxy_protocol;
+mod render;
+mod replay;
+mod stream;
 mod tee_reader;
+mod tls;
+mod udp;
+mod upstream;
+use capture::{Capture, CaptureFormat, Direction};
 use clap::{ArgAction, Parser};
-use futures::FutureExt;
+use decode::{Decoded, Protocol, StreamDecoder};
+use fault::Faults;
+use filter::Filter;
+use mapping::Mapping;
+use proxy_protocol::Version;
+use regex::bytes::Regex;
+use render::Format;
+use replay::Side;
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use stream::{Addr, Listener, Socket, Stream};
 use tokio::io;
 use tokio::io::AsyncWriteExt;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::UdpSocket;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use upstream::{Balance, Connect, Upstreams};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(long)]
+    /// `host:port` or `unix:/path`
+    #[arg(long, conflicts_with_all = ["map", "config"])]
     bind: Option<String>,
 
-    #[arg(long)]
+    /// upstream `host:port` or `unix:/path`, several separated by commas
+    #[arg(long, conflicts_with_all = ["map", "config"])]
     server: Option<String>,
 
-    #[clap(long, short, action=ArgAction::SetTrue)]
+    /// how to pick one of several upstreams
+    #[arg(long, value_enum, default_value_t = Balance::RoundRobin)]
+    balance: Balance,
+
+    /// milliseconds to wait for an upstream to accept
+    #[arg(long, default_value_t = 10000)]
+    connect_timeout: u64,
+
+    /// try all upstreams this many more times when all of them failed
+    #[arg(long, default_value_t = 0)]
+    connect_retries: u32,
+
+    /// milliseconds between two rounds of --connect-retries
+    #[arg(long, default_value_t = 1000)]
+    retry_delay: u64,
+
+    /// proxy several listeners at once, `[LABEL@]BIND=SERVER` (repeatable)
+    #[arg(long, value_parser = mapping::parse)]
+    map: Vec<Mapping>,
+
+    /// YAML file with a `mappings` list of {label, bind, server, protocol}
+    #[arg(long)]
+    config: Option<String>,
+
+    #[clap(long, short = 'i', action=ArgAction::SetTrue)]
     without_inbound: bool,
 
-    #[clap(long, short, action=ArgAction::SetTrue)]
+    #[clap(long, short = 'o', action=ArgAction::SetTrue)]
+    without_outbound: bool,
+
+    /// write every chunk to this file
+    #[arg(long)]
+    capture: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = CaptureFormat::Jsonl)]
+    capture_format: CaptureFormat,
+
+    #[arg(long, value_enum, default_value_t = Format::Auto)]
+    format: Format,
+
+    /// print at most this many bytes of each chunk
+    #[arg(long)]
+    max_bytes: Option<usize>,
+
+    /// decode messages of this protocol instead of printing raw chunks
+    #[arg(long, value_enum, default_value_t = Protocol::Raw)]
+    protocol: Protocol,
+
+    /// replay a JSONL capture instead of proxying
+    #[arg(long, conflicts_with_all = ["map", "config", "udp", "capture"])]
+    replay: Option<String>,
+
+    /// side of the capture to play, the other side is expected from the peer
+    #[arg(long, value_enum, default_value_t = Side::Client, requires = "replay")]
+    replay_as: Side,
+
+    /// speed up the recorded timing by this factor, 0 sends without delays
+    #[arg(long, default_value_t = 1.0, requires = "replay", value_parser = replay::parse_speed)]
+    speed: f64,
+
+    /// replace matches (or their capture groups) with [REDACTED] before printing (repeatable)
+    #[arg(long)]
+    redact: Vec<Regex>,
+
+    /// only print chunks matching one of these patterns (repeatable)
+    #[arg(long)]
+    include: Vec<Regex>,
+
+    /// do not print chunks matching one of these patterns (repeatable)
+    #[arg(long)]
+    exclude: Vec<Regex>,
+
+    /// forward each chunk this many milliseconds after it was read
+    #[arg(long, default_value_t = 0)]
+    latency: u64,
+
+    /// limit client -> server to this many bytes per second
+    #[arg(long)]
+    bandwidth_inbound: Option<u64>,
+
+    /// limit server -> client to this many bytes per second
+    #[arg(long)]
+    bandwidth_outbound: Option<u64>,
+
+    /// reset connections after this many bytes (both directions together)
+    #[arg(long)]
+    reset_after: Option<u64>,
+
+    /// share of connections that get reset by --reset-after
+    #[arg(long, default_value_t = 1.0, requires = "reset_after", value_parser = fault::parse_probability)]
+    reset_probability: f64,
+
+    /// chance of each chunk to stall for --stall milliseconds
+    #[arg(long, default_value_t = 0.0, value_parser = fault::parse_probability)]
+    stall_probability: f64,
+
+    /// milliseconds a stalled chunk waits
+    #[arg(long, default_value_t = 5000)]
+    stall: u64,
+
+    /// forward UDP datagrams instead of TCP connections
+    #[arg(long, conflicts_with_all = [
+        "tls", "upstream_tls", "protocol", "balance", "latency", "bandwidth_inbound",
+        "bandwidth_outbound", "reset_after", "stall_probability",
+    ])]
+    udp: bool,
+
+    /// seconds without datagrams after which a UDP session is dropped
+    #[arg(long, default_value_t = 60)]
+    udp_idle_timeout: u64,
+
+    /// datagrams of new UDP clients are dropped while this many sessions are open
+    #[arg(long, default_value_t = 1024)]
+    udp_max_sessions: usize,
+
+    /// expect a PROXY protocol v1 or v2 header from clients
+    #[arg(long, conflicts_with_all = ["udp", "replay"])]
+    proxy_protocol_in: bool,
+
+    /// send a PROXY protocol header with the original client to the upstream
+    #[arg(long, value_enum, conflicts_with_all = ["udp", "replay"])]
+    proxy_protocol_out: Option<Version>,
+
+    /// terminate TLS from clients
+    #[arg(long)]
+    tls: bool,
+
+    /// PEM certificate chain for --tls, a self-signed one is generated when omitted
+    #[arg(long, requires_all = ["tls", "tls_key"])]
+    tls_cert: Option<String>,
+
+    /// PEM private key for --tls
+    #[arg(long, requires_all = ["tls", "tls_cert"])]
+    tls_key: Option<String>,
+
+    /// names of the generated certificate
+    #[arg(long, default_value = "localhost")]
+    tls_hostname: Vec<String>,
+
+    /// connect to --server with TLS
+    #[arg(long)]
+    upstream_tls: bool,
+
+    /// PEM CA certificates trusted for the upstream, in addition to the web PKI roots
+    #[arg(long)]
+    upstream_ca: Option<String>,
+
+    /// do not verify the upstream certificate
+    #[arg(long)]
+    upstream_insecure: bool,
+
+    /// SNI / verified name of the upstream, defaults to the host of --server
+    #[arg(long)]
+    upstream_sni: Option<String>,
+}
+
+/// Shared by every connection.
+struct Settings {
+    without_inbound: bool,
     without_outbound: bool,
+    capture: Option<Arc<Capture>>,
+    format: Format,
+    max_bytes: Option<usize>,
+    tls_acceptor: Option<TlsAcceptor>,
+    faults: Faults,
+    filter: Filter,
+    proxy_protocol_in: bool,
+    proxy_protocol_out: Option<Version>,
+}
+
+/// Shared by the connections of one mapping.
+struct Route {
+    label: Option<String>,
+    upstreams: Upstreams,
+    protocol: Protocol,
+    tls_connector: Option<TlsConnector>,
+}
+
+/// Prefix of every line printed for one accepted connection.
+struct Connection {
+    route: Arc<Route>,
+    id: u64,
+    peer: Addr,
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.route.label.as_ref() {
+            Some(label) => write!(f, "[{} #{} {}]", label, self.id, self.peer),
+            None => write!(f, "[#{} {}]", self.id, self.peer),
+        }
+    }
+}
+
+/// Bytes seen in each direction, kept even when the transfer fails half way.
+#[derive(Default)]
+struct Counters {
+    inbound: AtomicU64,
+    outbound: AtomicU64,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
-    let listen_addr = args.bind.unwrap_or_else(|| "127.0.0.1:8081".to_string());
-    let server_addr = args.server.unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let mut mappings = match args.config.as_ref() {
+        Some(path) => mapping::load(path)?,
+        None => Vec::new(),
+    };
+    mappings.extend(args.map.iter().cloned());
+    if args.config.is_none() && args.map.is_empty() {
+        mappings.push(Mapping {
+            label: None,
+            bind: args.bind.unwrap_or_else(|| "127.0.0.1:8081".to_string()),
+            server: args.server.unwrap_or_else(|| "127.0.0.1:8080".to_string()),
+            protocol: None,
+        });
+    }
+    if mappings.is_empty() {
+        return Err("no mappings configured".into());
+    }
 
-    println!("Listening on: {}", listen_addr);
-    println!("Proxying to: {}", server_addr);
+    let capture = match args.capture.as_ref() {
+        Some(path) => Some(Arc::new(Capture::create(path, args.capture_format)?)),
+        None => None,
+    };
+    let tls_acceptor = if args.tls {
+        Some(tls::acceptor(
+            args.tls_cert.as_deref(),
+            args.tls_key.as_deref(),
+            &args.tls_hostname,
+        )?)
+    } else {
+        None
+    };
+    let tls_connector = if args.upstream_tls {
+        Some(tls::connector(
+            args.upstream_ca.as_deref(),
+            args.upstream_insecure,
+        )?)
+    } else {
+        None
+    };
+    let settings = Arc::new(Settings {
+        without_inbound: args.without_inbound,
+        without_outbound: args.without_outbound,
+        capture,
+        format: args.format,
+        max_bytes: args.max_bytes,
+        tls_acceptor,
+        faults: Faults {
+            latency: Duration::from_millis(args.latency),
+            bandwidth_inbound: args.bandwidth_inbound,
+            bandwidth_outbound: args.bandwidth_outbound,
+            reset_after: args.reset_after,
+            reset_probability: args.reset_probability,
+            stall_probability: args.stall_probability,
+            stall: Duration::from_millis(args.stall),
+        },
+        filter: Filter {
+            redact: args.redact,
+            include: args.include,
+            exclude: args.exclude,
+        },
+        proxy_protocol_in: args.proxy_protocol_in,
+        proxy_protocol_out: args.proxy_protocol_out,
+    });
 
-    let listener = TcpListener::bind(listen_addr).await?;
+    let connect = Connect {
+        balance: args.balance,
+        timeout: Duration::from_millis(args.connect_timeout),
+        retries: args.connect_retries,
+        retry_delay: Duration::from_millis(args.retry_delay),
+    };
 
-    while let Ok((inbound, _)) = listener.accept().await {
-        let transfer = transfer(
-            inbound,
-            server_addr.clone(),
-            args.without_inbound,
-            args.without_outbound,
-        )
-        .map(|r| {
-            if let Err(e) = r {
-                println!("Failed to transfer; error={}", e);
+    // ids are unique across mappings so they also identify connections in the capture
+    let next_id = Arc::new(AtomicU64::new(0));
+    let labelled = mappings.len() > 1;
+    let mut servers = Vec::new();
+    for mapping in mappings {
+        let upstreams = Upstreams::new(
+            &mapping.server,
+            connect.clone(),
+            tls_connector.is_some(),
+            args.upstream_sni.as_deref(),
+        )?;
+        let label = match mapping.label {
+            Some(label) => Some(label),
+            None if labelled => Some(mapping.bind.clone()),
+            None => None,
+        };
+        if let Some(path) = args.replay.as_ref() {
+            let replay = replay::run(
+                path,
+                args.replay_as,
+                args.speed,
+                &mapping.bind,
+                Arc::new(Route {
+                    label,
+                    upstreams,
+                    protocol: mapping.protocol.unwrap_or(args.protocol),
+                    tls_connector: tls_connector.clone(),
+                }),
+                settings,
+            );
+            // dropping the replay removes the socket file of a unix listener
+            return tokio::select! {
+                result = replay => result,
+                result = shutdown() => result,
+            };
+        }
+        let route = Arc::new(Route {
+            label,
+            upstreams,
+            protocol: mapping.protocol.unwrap_or(args.protocol),
+            tls_connector: tls_connector.clone(),
+        });
+        let listener = if args.udp {
+            if stream::unix_path(&mapping.bind).is_some() {
+                return Err("--udp does not support unix sockets".into());
+            }
+            let upstreams = udp::resolve(&route).await?;
+            Listeners::Udp(UdpSocket::bind(&mapping.bind).await?, upstreams)
+        } else {
+            Listeners::Stream(Listener::bind(&mapping.bind).await?)
+        };
+        match route.label.as_ref() {
+            Some(label) => println!(
+                "[{}] Listening on: {} Proxying to: {}",
+                label, mapping.bind, mapping.server
+            ),
+            None => {
+                println!("Listening on: {}", mapping.bind);
+                println!("Proxying to: {}", mapping.server);
+            }
+        }
+        let (settings, next_id) = (settings.clone(), next_id.clone());
+        servers.push(match listener {
+            Listeners::Stream(listener) => tokio::spawn(serve(listener, route, settings, next_id)),
+            Listeners::Udp(socket, upstreams) => {
+                let limits = udp::Limits {
+                    idle_timeout: Duration::from_secs(args.udp_idle_timeout),
+                    max_sessions: args.udp_max_sessions,
+                };
+                tokio::spawn(udp::serve(
+                    socket, route, upstreams, settings, next_id, limits,
+                ))
             }
         });
-
-        tokio::spawn(transfer);
+    }
+    tokio::select! {
+        _ = futures::future::join_all(&mut servers) => {}
+        result = shutdown() => result?,
+    }
+    // dropping the listeners removes the socket files of unix listeners
+    for server in servers.iter() {
+        server.abort();
+    }
+    futures::future::join_all(servers).await;
+    if let Some(capture) = settings.capture.as_ref() {
+        capture.flush().await;
     }
 
     Ok(())
 }
 
+/// Waits for SIGINT or SIGTERM.
+async fn shutdown() -> Result<(), Box<dyn Error>> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    }
+    Ok(())
+}
+
+enum Listeners {
+    Stream(Listener),
+    /// with the upstreams from `udp::resolve`
+    Udp(UdpSocket, HashMap<String, SocketAddr>),
+}
+
+/// Accepts connections of one mapping until the listener fails.
+async fn serve(
+    listener: Listener,
+    route: Arc<Route>,
+    settings: Arc<Settings>,
+    next_id: Arc<AtomicU64>,
+) {
+    while let Ok((inbound, peer)) = listener.accept().await {
+        let conn = Connection {
+            route: route.clone(),
+            id: next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            peer,
+        };
+        let settings = settings.clone();
+        tokio::spawn(async move {
+            let counters = Counters::default();
+            report(
+                &conn,
+                &counters,
+                transfer(&conn, &counters, inbound, &settings),
+            )
+            .await;
+        });
+    }
+}
+
+/// Prints the open event, runs `f` and prints how the connection ended.
+async fn report(
+    conn: &Connection,
+    counters: &Counters,
+    f: impl Future<Output = Result<(), Box<dyn Error + Send + Sync>>>,
+) {
+    println!("{} open", conn);
+    let started = Instant::now();
+    let result = f.await;
+    let summary = format!(
+        "duration={:?} inbound={} outbound={}",
+        started.elapsed(),
+        counters.inbound.load(Ordering::Relaxed),
+        counters.outbound.load(Ordering::Relaxed),
+    );
+    match result {
+        Ok(()) => println!("{} close {}", conn, summary),
+        Err(e) => println!("{} error {} error={}", conn, summary, e),
+    }
+}
+
+/// `offset` is where `buf` starts in the stream. An empty `buf` marks the end
+/// of the stream and prints what the decoder held back.
+fn print_chunk(
+    conn: &Connection,
+    settings: &Settings,
+    decoder: &mut StreamDecoder,
+    mark: &str,
+    buf: &[u8],
+    offset: u64,
+) {
+    let decoded = match buf {
+        [] => decoder.finish().into_iter().collect(),
+        buf => decoder.feed(buf),
+    };
+    let end = offset + buf.len() as u64 - decoder.held() as u64;
+    let mut lines = Vec::new();
+    for decoded in decoded {
+        match decoded {
+            Decoded::Lines(decoded) => lines.extend(
+                decoded
+                    .into_iter()
+                    .map(|line| settings.filter.redact_line(line)),
+            ),
+            Decoded::Raw(raw) => lines.extend(render::lines(
+                settings.format,
+                &settings.filter.redact(&raw),
+                end - raw.len() as u64,
+                settings.max_bytes,
+            )),
+        }
+    }
+    if lines.is_empty() || !settings.filter.shows(buf, &lines) {
+        return;
+    }
+    for line in lines {
+        println!("{} {} {}", conn, mark, line);
+    }
+}
+
 async fn transfer(
-    mut inbound: TcpStream,
-    proxy_addr: String,
-    without_inbound: bool,
-    without_outbound: bool,
-) -> Result<(), Box<dyn Error>> {
-    let mut outbound = TcpStream::connect(proxy_addr).await?;
+    conn: &Connection,
+    counters: &Counters,
+    mut inbound: Socket,
+    settings: &Settings,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // the original connection when behind a proxy, ours otherwise (none for unix sockets)
+    let mut original = match (conn.peer.inet(), inbound.local_addr()?.inet()) {
+        (Some(source), Some(destination)) => Some(proxy_protocol::Header {
+            source,
+            destination,
+        }),
+        _ => None,
+    };
+    if settings.proxy_protocol_in {
+        let header = tokio::time::timeout(
+            proxy_protocol::HEADER_TIMEOUT,
+            proxy_protocol::read(&mut inbound),
+        )
+        .await
+        .map_err(|_| {
+            format!(
+                "no PROXY protocol header within {:?}",
+                proxy_protocol::HEADER_TIMEOUT
+            )
+        })??;
+        if let Some(header) = header {
+            println!(
+                "{} proxy source={} destination={}",
+                conn, header.source, header.destination
+            );
+            original = Some(header);
+        }
+    }
+
+    let (mut outbound, upstream) = conn
+        .route
+        .upstreams
+        .connect(|upstream, e| {
+            println!(
+                "{} connect failed upstream={} error={}",
+                conn, upstream.addr, e
+            )
+        })
+        .await?;
+    if conn.route.upstreams.len() > 1 {
+        println!("{} upstream {}", conn, upstream.addr);
+    }
+    let capture = match settings.capture.as_ref() {
+        Some(capture) => Some(capture.connection(
+            conn.id,
+            original.map(|header| header.source),
+            outbound.peer_addr()?.inet(),
+        )),
+        None => None,
+    };
+    if let Some(version) = settings.proxy_protocol_out {
+        outbound
+            .write_all(&proxy_protocol::encode(version, original.as_ref()))
+            .await?;
+    }
 
-    let (ri, mut wi) = inbound.split();
-    let (ro, mut wo) = outbound.split();
+    let plan = settings.faults.plan();
+    let mut reset_handles = Vec::new();
+    let (inbound, outbound) = if plan.resets() {
+        let (inbound, inbound_handle) = fault::reset_handle(inbound)?;
+        let (outbound, outbound_handle) = fault::reset_handle(outbound)?;
+        reset_handles.extend(inbound_handle.into_iter().chain(outbound_handle));
+        (inbound, outbound)
+    } else {
+        (inbound, outbound)
+    };
+
+    let inbound: Box<dyn Stream> = match settings.tls_acceptor.as_ref() {
+        Some(acceptor) => Box::new(acceptor.accept(inbound).await?),
+        None => Box::new(inbound),
+    };
+    let outbound: Box<dyn Stream> = match (conn.route.tls_connector.as_ref(), &upstream.sni) {
+        (Some(connector), Some(name)) => Box::new(connector.connect(name.clone(), outbound).await?),
+        _ => Box::new(outbound),
+    };
+
+    let (ri, mut wi) = io::split(inbound);
+    let (ro, mut wo) = io::split(outbound);
+
+    let whole_lines = !settings.filter.redact.is_empty();
+    let (mut inbound_decoder, mut outbound_decoder) =
+        decode::pair(conn.route.protocol, whole_lines);
 
     let mut ri = tee_reader::TeeReader::new(ri, |buf| {
-        if without_inbound || buf.is_empty() {
-            return;
+        let offset = counters
+            .inbound
+            .fetch_add(buf.len() as u64, Ordering::Relaxed);
+        if let Some(capture) = capture.as_ref() {
+            capture.chunk(Direction::Inbound, buf);
         }
-        match std::str::from_utf8(buf) {
-            Ok(s) => {
-                s.split('\n').for_each(|x| println!("> {}", x));
-            }
-            Err(_) => {
-                println!("> {:?}", &buf[..std::cmp::min(256, buf.len())]);
-            }
+        if settings.without_inbound {
+            return;
         }
+        print_chunk(conn, settings, &mut inbound_decoder, ">", buf, offset);
     });
 
     let mut ro = tee_reader::TeeReader::new(ro, |buf| {
-        if without_outbound || buf.is_empty() {
-            return;
+        let offset = counters
+            .outbound
+            .fetch_add(buf.len() as u64, Ordering::Relaxed);
+        if let Some(capture) = capture.as_ref() {
+            capture.chunk(Direction::Outbound, buf);
         }
-        match std::str::from_utf8(buf) {
-            Ok(s) => {
-                s.split('\n').for_each(|x| println!("< {}", x));
-            }
-            Err(_) => {
-                println!("< {:?}", &buf[..std::cmp::min(256, buf.len())]);
-            }
+        if settings.without_outbound {
+            return;
         }
+        print_chunk(conn, settings, &mut outbound_decoder, "<", buf, offset);
     });
 
     let client_to_server = async {
-        io::copy(&mut ri, &mut wo).await?;
+        fault::copy(
+            &settings.faults,
+            &plan,
+            Direction::Inbound,
+            &mut ri,
+            &mut wo,
+        )
+        .await?;
         wo.shutdown().await
     };
 
     let server_to_client = async {
-        io::copy(&mut ro, &mut wi).await?;
+        fault::copy(
+            &settings.faults,
+            &plan,
+            Direction::Outbound,
+            &mut ro,
+            &mut wi,
+        )
+        .await?;
         wi.shutdown().await
     };
 
-    tokio::try_join!(client_to_server, server_to_client)?;
+    let result = tokio::try_join!(client_to_server, server_to_client);
+    // an empty read printed the rest already unless the transfer failed
+    drop((ri, ro));
+    if !settings.without_inbound {
+        let offset = counters.inbound.load(Ordering::Relaxed);
+        print_chunk(conn, settings, &mut inbound_decoder, ">", &[], offset);
+    }
+    if !settings.without_outbound {
+        let offset = counters.outbound.load(Ordering::Relaxed);
+        print_chunk(conn, settings, &mut outbound_decoder, "<", &[], offset);
+    }
+    if plan.was_reset() {
+        for handle in reset_handles {
+            handle.set_linger(Some(Duration::ZERO))?;
+        }
+    }
+    result?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn args() {
+        Args::command().debug_assert();
+    }
+}
@@ -0,0 +1,88 @@
+use crate::decode::Protocol;
+use serde::Deserialize;
+use std::error::Error;
+
+/// A listener and the upstream its connections are proxied to.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    /// shown in front of every line, defaults to `bind` when there are several mappings
+    pub label: Option<String>,
+    pub bind: String,
+    pub server: String,
+    /// overrides `--protocol`
+    pub protocol: Option<Protocol>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    mappings: Vec<Mapping>,
+}
+
+/// `[LABEL@]BIND=SERVER`, e.g. `db@0.0.0.0:5433=db:5432`. An `@` belongs to
+/// the bind address (e.g. a unix path) when the text before it has `=`, `:` or `/`.
+pub fn parse(s: &str) -> Result<Mapping, String> {
+    let (label, rest) = match s.split_once('@') {
+        Some((label, rest)) if !label.contains(['=', ':', '/']) => (Some(label.to_string()), rest),
+        _ => (None, s),
+    };
+    let (bind, server) = rest
+        .split_once('=')
+        .ok_or_else(|| format!("expected [LABEL@]BIND=SERVER, got {:?}", s))?;
+    if bind.is_empty() || server.is_empty() {
+        return Err(format!("expected [LABEL@]BIND=SERVER, got {:?}", s));
+    }
+    Ok(Mapping {
+        label,
+        bind: bind.to_string(),
+        server: server.to_string(),
+        protocol: None,
+    })
+}
+
+/// Reads the `mappings` list of a YAML config file.
+pub fn load(path: &str) -> Result<Vec<Mapping>, Box<dyn Error>> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let config: Config = serde_yaml::from_reader(file).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(config.mappings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mappings() {
+        assert_eq!(
+            parse("db@0.0.0.0:5433=db:5432").unwrap(),
+            Mapping {
+                label: Some("db".to_string()),
+                bind: "0.0.0.0:5433".to_string(),
+                server: "db:5432".to_string(),
+                protocol: None,
+            }
+        );
+        assert_eq!(parse("[::]:80=api:80").unwrap().bind, "[::]:80");
+        assert!(parse("0.0.0.0:5433").is_err());
+        assert_eq!(
+            parse("/run/a@b.sock=db:5432").unwrap(),
+            Mapping {
+                label: None,
+                bind: "/run/a@b.sock".to_string(),
+                server: "db:5432".to_string(),
+                protocol: None,
+            }
+        );
+        let mapping = parse("db@/run/a@b.sock=db:5432").unwrap();
+        assert_eq!(mapping.label.as_deref(), Some("db"));
+        assert_eq!(mapping.bind, "/run/a@b.sock");
+        assert_eq!(parse("0.0.0.0:5433=user@db:5432").unwrap().label, None);
+
+        let config: Config = serde_yaml::from_str(
+            "mappings:\n  - label: cache\n    bind: 0.0.0.0:6380\n    server: redis:6379\n    protocol: redis\n",
+        )
+        .unwrap();
+        assert_eq!(config.mappings[0].protocol, Some(Protocol::Redis));
+    }
+}
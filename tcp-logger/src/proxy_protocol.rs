@@ -0,0 +1,233 @@
+use clap::ValueEnum;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncReadExt};
+
+/// HAProxy PROXY protocol, see
+/// https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Version {
+    /// text header, `PROXY TCP4 ...\r\n`
+    V1,
+    /// binary header
+    V2,
+}
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// How long a client may take to send its header.
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest v1 header including `\r\n`.
+const V1_MAX: usize = 107;
+
+/// Addresses of the original connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+/// Reads a v1 or v2 header without consuming anything after it. `None` for
+/// headers without addresses (`UNKNOWN`, `LOCAL`, non-IP families).
+pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Header>> {
+    let mut buf = vec![0; 5];
+    reader.read_exact(&mut buf).await?;
+    if buf == b"PROXY" {
+        // byte by byte so nothing after the line is consumed
+        while !buf.ends_with(b"\r\n") {
+            if buf.len() == V1_MAX {
+                return Err(invalid("PROXY v1 header too long"));
+            }
+            buf.push(reader.read_u8().await?);
+        }
+        let line = std::str::from_utf8(&buf[..buf.len() - 2])
+            .map_err(|_| invalid("PROXY v1 header is not ASCII"))?;
+        return parse_v1(line);
+    }
+    if buf[..] == V2_SIGNATURE[..5] {
+        buf.resize(16, 0);
+        reader.read_exact(&mut buf[5..]).await?;
+        if buf[..12] != V2_SIGNATURE[..] {
+            return Err(invalid("invalid PROXY v2 signature"));
+        }
+        let len = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+        buf.resize(16 + len, 0);
+        reader.read_exact(&mut buf[16..]).await?;
+        return parse_v2(&buf);
+    }
+    Err(invalid("missing PROXY protocol header"))
+}
+
+fn parse_v1(line: &str) -> io::Result<Option<Header>> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, destination, source_port, destination_port] => {
+            let addr = |ip: &str, port: &str| -> io::Result<SocketAddr> {
+                let ip: IpAddr = ip
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid address {:?}", ip)))?;
+                let port: u16 = port
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid port {:?}", port)))?;
+                Ok(SocketAddr::new(ip, port))
+            };
+            Ok(Some(Header {
+                source: addr(source, source_port)?,
+                destination: addr(destination, destination_port)?,
+            }))
+        }
+        _ => Err(invalid(format!("invalid PROXY v1 header {:?}", line))),
+    }
+}
+
+fn parse_v2(buf: &[u8]) -> io::Result<Option<Header>> {
+    let version_command = buf[12];
+    if version_command >> 4 != 2 {
+        return Err(invalid(format!(
+            "unsupported PROXY version {}",
+            version_command >> 4
+        )));
+    }
+    match version_command & 0x0f {
+        0 => return Ok(None),
+        1 => {}
+        command => return Err(invalid(format!("unsupported PROXY v2 command {}", command))),
+    }
+    let addresses = &buf[16..];
+    let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+    // the high nibble is the family, TLVs after the addresses are ignored
+    match buf[13] >> 4 {
+        1 if addresses.len() >= 12 => {
+            let ip = |at: usize| {
+                let octets: [u8; 4] = addresses[at..at + 4].try_into().unwrap();
+                IpAddr::from(octets)
+            };
+            Ok(Some(Header {
+                source: SocketAddr::new(ip(0), port(8)),
+                destination: SocketAddr::new(ip(4), port(10)),
+            }))
+        }
+        2 if addresses.len() >= 36 => {
+            let ip = |at: usize| {
+                let octets: [u8; 16] = addresses[at..at + 16].try_into().unwrap();
+                IpAddr::from(octets)
+            };
+            Ok(Some(Header {
+                source: SocketAddr::new(ip(0), port(32)),
+                destination: SocketAddr::new(ip(16), port(34)),
+            }))
+        }
+        1 | 2 => Err(invalid("PROXY v2 addresses too short")),
+        _ => Ok(None),
+    }
+}
+
+/// Both addresses in the same family, IPv4 ones mapped to IPv6 when needed.
+fn same_family(header: &Header) -> (IpAddr, IpAddr) {
+    match (header.source.ip(), header.destination.ip()) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => (s.into(), d.into()),
+        (s, d) => {
+            let to_v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            (to_v6(s).into(), to_v6(d).into())
+        }
+    }
+}
+
+/// Without a header (unix domain sockets) `UNKNOWN` / `LOCAL` is sent.
+pub fn encode(version: Version, header: Option<&Header>) -> Vec<u8> {
+    let Some(header) = header else {
+        return match version {
+            Version::V1 => b"PROXY UNKNOWN\r\n".to_vec(),
+            Version::V2 => {
+                let mut buf = V2_SIGNATURE.to_vec();
+                buf.extend_from_slice(&[0x20, 0x00, 0, 0]);
+                buf
+            }
+        };
+    };
+    let (source, destination) = same_family(header);
+    let (source_port, destination_port) = (header.source.port(), header.destination.port());
+    match version {
+        Version::V1 => {
+            let family = if source.is_ipv4() { "TCP4" } else { "TCP6" };
+            format!(
+                "PROXY {} {} {} {} {}\r\n",
+                family, source, destination, source_port, destination_port
+            )
+            .into_bytes()
+        }
+        Version::V2 => {
+            let mut addresses = Vec::new();
+            let family = match (source, destination) {
+                (IpAddr::V4(s), IpAddr::V4(d)) => {
+                    addresses.extend_from_slice(&s.octets());
+                    addresses.extend_from_slice(&d.octets());
+                    0x11
+                }
+                (IpAddr::V6(s), IpAddr::V6(d)) => {
+                    addresses.extend_from_slice(&s.octets());
+                    addresses.extend_from_slice(&d.octets());
+                    0x21
+                }
+                _ => unreachable!("same_family returns one family"),
+            };
+            addresses.extend_from_slice(&source_port.to_be_bytes());
+            addresses.extend_from_slice(&destination_port.to_be_bytes());
+
+            let mut buf = V2_SIGNATURE.to_vec();
+            buf.push(0x21);
+            buf.push(family);
+            buf.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+            buf.extend_from_slice(&addresses);
+            buf
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let header = Header {
+            source: "192.0.2.1:56324".parse().unwrap(),
+            destination: "198.51.100.2:443".parse().unwrap(),
+        };
+        let v1 = encode(Version::V1, Some(&header));
+        assert_eq!(v1, b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\n");
+
+        for version in [Version::V1, Version::V2] {
+            let mut buf = encode(version, Some(&header));
+            buf.extend_from_slice(b"GET /");
+            let mut reader = &buf[..];
+            assert_eq!(read(&mut reader).await.unwrap(), Some(header));
+            assert_eq!(reader, b"GET /");
+        }
+
+        let mixed = Header {
+            source: "[2001:db8::1]:1234".parse().unwrap(),
+            destination: "10.0.0.1:80".parse().unwrap(),
+        };
+        let mut reader = &encode(Version::V2, Some(&mixed))[..];
+        let decoded = read(&mut reader).await.unwrap().unwrap();
+        assert_eq!(decoded.destination.to_string(), "[::ffff:10.0.0.1]:80");
+
+        for version in [Version::V1, Version::V2] {
+            let mut reader = &encode(version, None)[..];
+            assert_eq!(read(&mut reader).await.unwrap(), None);
+        }
+        let mut reader: &[u8] = b"GET / HTTP/1.1\r\n";
+        assert!(read(&mut reader).await.is_err());
+    }
+}
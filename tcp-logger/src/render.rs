@@ -0,0 +1,95 @@
+use clap::ValueEnum;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// text for printable chunks, hexdump otherwise
+    Auto,
+    /// split on newlines, invalid UTF-8 is replaced
+    Text,
+    /// offset / hex / ASCII like `hexdump -C`
+    Hex,
+}
+
+/// Lines to print for one chunk. `offset` is where the chunk starts in the
+/// stream and is used for the hexdump offsets.
+pub fn lines(format: Format, buf: &[u8], offset: u64, max_bytes: Option<usize>) -> Vec<String> {
+    let shown = &buf[..max_bytes.map_or(buf.len(), |max| max.min(buf.len()))];
+    let mut lines = match format {
+        Format::Auto if is_printable(buf) => text(shown),
+        Format::Text => text(shown),
+        Format::Auto | Format::Hex => hexdump(shown, offset),
+    };
+    if shown.len() < buf.len() {
+        lines.push(format!("... {} more bytes", buf.len() - shown.len()));
+    }
+    lines
+}
+
+/// Valid UTF-8 without control characters other than `\r`, `\n` and `\t`.
+pub fn is_printable(buf: &[u8]) -> bool {
+    std::str::from_utf8(buf).is_ok_and(|s| {
+        s.chars()
+            .all(|c| !c.is_control() || matches!(c, '\r' | '\n' | '\t'))
+    })
+}
+
+fn text(buf: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(buf)
+        .split('\n')
+        .map(|line| line.to_string())
+        .collect()
+}
+
+fn hexdump(buf: &[u8], offset: u64) -> Vec<String> {
+    buf.chunks(16)
+        .enumerate()
+        .map(|(i, row)| {
+            let mut line = format!("{:08x} ", offset + i as u64 * 16);
+            for j in 0..16 {
+                if j == 8 {
+                    line.push(' ');
+                }
+                match row.get(j) {
+                    Some(b) => write!(line, " {:02x}", b).unwrap(),
+                    None => line.push_str("   "),
+                }
+            }
+            line.push_str("  |");
+            line.extend(row.iter().map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            }));
+            line.push('|');
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        assert_eq!(
+            lines(Format::Hex, b"GET / HTTP/1.1\r\nHost", 16, None),
+            vec![
+                "00000010  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|",
+                "00000020  48 6f 73 74                                       |Host|",
+            ]
+        );
+        assert_eq!(
+            lines(Format::Auto, b"a\nb", 0, None),
+            vec!["a".to_string(), "b".to_string()]
+        );
+        assert_eq!(lines(Format::Auto, b"\x00\x01", 0, None).len(), 1);
+        assert_eq!(
+            lines(Format::Text, b"abcdef", 0, Some(3)),
+            vec!["abc".to_string(), "... 3 more bytes".to_string()]
+        );
+    }
+}
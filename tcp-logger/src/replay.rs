@@ -0,0 +1,327 @@
+use crate::capture::Direction;
+use crate::decode::{self, StreamDecoder};
+use crate::stream::{Addr, Listener, Stream};
+use crate::upstream::Upstream;
+use crate::{print_chunk, report, Connection, Counters, Route, Settings};
+use base64::Engine;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::error::Error;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::watch;
+
+/// How long the peer may stay silent before a chunk of its side counts as
+/// received although it sent fewer bytes than recorded.
+const QUIET: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Side {
+    /// connect to --server and send the recorded client -> server chunks
+    Client,
+    /// listen on --bind and answer with the recorded server -> client chunks
+    Server,
+}
+
+impl Side {
+    fn sends(self) -> Direction {
+        match self {
+            Side::Client => Direction::Inbound,
+            Side::Server => Direction::Outbound,
+        }
+    }
+}
+
+/// One line of a JSONL capture.
+#[derive(Deserialize)]
+struct Record {
+    connection: u64,
+    direction: Direction,
+    timestamp: f64,
+    offset: u64,
+    payload: String,
+}
+
+struct Chunk {
+    direction: Direction,
+    timestamp: f64,
+    /// stream offset after this chunk
+    end: u64,
+    payload: Vec<u8>,
+}
+
+/// Chunks of each recorded connection, in the order the connections appear.
+fn load(path: &str) -> Result<Vec<Vec<Chunk>>, Box<dyn Error>> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut ids = Vec::new();
+    let mut connections: Vec<Vec<Chunk>> = Vec::new();
+    for (i, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record =
+            serde_json::from_str(&line).map_err(|e| format!("{}:{}: {}", path, i + 1, e))?;
+        let payload = base64::engine::general_purpose::STANDARD
+            .decode(&record.payload)
+            .map_err(|e| format!("{}:{}: {}", path, i + 1, e))?;
+        let chunk = Chunk {
+            direction: record.direction,
+            timestamp: record.timestamp,
+            end: record.offset + payload.len() as u64,
+            payload,
+        };
+        match ids.iter().position(|&id| id == record.connection) {
+            Some(index) => connections[index].push(chunk),
+            None => {
+                ids.push(record.connection);
+                connections.push(vec![chunk]);
+            }
+        }
+    }
+    if connections.is_empty() {
+        return Err(format!("{}: no chunks recorded", path).into());
+    }
+    Ok(connections)
+}
+
+/// Replays a JSONL capture as `side`. `speed` scales the recorded delays
+/// (2 is twice as fast), 0 sends without delays.
+pub async fn run(
+    path: &str,
+    side: Side,
+    speed: f64,
+    bind: &str,
+    route: Arc<Route>,
+    settings: Arc<Settings>,
+) -> Result<(), Box<dyn Error>> {
+    let connections = load(path)?;
+    println!("Replaying {} connections from {}", connections.len(), path);
+    let connections: Arc<Vec<Vec<Chunk>>> = Arc::new(connections);
+    let next_id = AtomicU64::new(0);
+    match side {
+        Side::Client => {
+            let first = connections[0][0].timestamp;
+            let mut replays = Vec::new();
+            for index in 0..connections.len() {
+                // connections start with their recorded offsets as well
+                let delay = delay(connections[index][0].timestamp - first, speed);
+                let id = next_id.fetch_add(1, Ordering::Relaxed) + 1;
+                let (connections, route, settings) =
+                    (connections.clone(), route.clone(), settings.clone());
+                replays.push(tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let failed = |upstream: &Upstream, e: &io::Error| {
+                        println!("Failed to connect to {}; error={}", upstream.addr, e)
+                    };
+                    let (stream, upstream) = match route.upstreams.connect(failed).await {
+                        Ok(connected) => connected,
+                        Err(e) => {
+                            println!("Failed to connect; error={}", e);
+                            return;
+                        }
+                    };
+                    let conn = Connection {
+                        route: route.clone(),
+                        id,
+                        peer: stream
+                            .local_addr()
+                            .unwrap_or(Addr::Inet(([0, 0, 0, 0], 0).into())),
+                    };
+                    let counters = Counters::default();
+                    let play = async {
+                        let stream: Box<dyn Stream> =
+                            match (route.tls_connector.as_ref(), &upstream.sni) {
+                                (Some(connector), Some(name)) => {
+                                    Box::new(connector.connect(name.clone(), stream).await?)
+                                }
+                                _ => Box::new(stream),
+                            };
+                        let chunks = &connections[index];
+                        play(&conn, &counters, stream, chunks, side, speed, &settings).await
+                    };
+                    report(&conn, &counters, play).await;
+                }));
+            }
+            futures::future::join_all(replays).await;
+        }
+        Side::Server => {
+            let listener = Listener::bind(bind).await?;
+            println!("Listening on: {}", bind);
+            // accepted connections take the recorded ones in turn
+            while let Ok((stream, peer)) = listener.accept().await {
+                let id = next_id.fetch_add(1, Ordering::Relaxed) + 1;
+                let index = (id - 1) as usize % connections.len();
+                let conn = Connection {
+                    route: route.clone(),
+                    id,
+                    peer,
+                };
+                let (connections, settings) = (connections.clone(), settings.clone());
+                tokio::spawn(async move {
+                    let counters = Counters::default();
+                    let play = async {
+                        let stream: Box<dyn Stream> = match settings.tls_acceptor.as_ref() {
+                            Some(acceptor) => Box::new(acceptor.accept(stream).await?),
+                            None => Box::new(stream),
+                        };
+                        let chunks = &connections[index];
+                        play(&conn, &counters, stream, chunks, side, speed, &settings).await
+                    };
+                    report(&conn, &counters, play).await;
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses `--speed`.
+pub fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if !speed.is_finite() || speed < 0.0 {
+        return Err(format!("{} is not a finite number >= 0", s));
+    }
+    Ok(speed)
+}
+
+/// Delays too long for a `Duration` (a tiny `speed`) are clamped.
+fn delay(secs: f64, speed: f64) -> Duration {
+    if speed <= 0.0 || secs <= 0.0 {
+        return Duration::ZERO;
+    }
+    Duration::try_from_secs_f64(secs / speed).unwrap_or(Duration::MAX)
+}
+
+/// Sends the chunks of our side with the recorded delays between them. Before
+/// going past a chunk of the other side, waits until the peer has sent as
+/// many bytes, so replies are not sent before the request arrived. A peer
+/// that sent something shorter (e.g. another URL) is let through after
+/// `QUIET`.
+async fn play(
+    conn: &Connection,
+    counters: &Counters,
+    stream: Box<dyn Stream>,
+    chunks: &[Chunk],
+    side: Side,
+    speed: f64,
+    settings: &Settings,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let sends = side.sends();
+    let (mut reader, mut writer) = io::split(stream);
+    // bytes received from the peer, u64::MAX once it closed its side
+    let (received_tx, mut received_rx) = watch::channel(0u64);
+
+    let whole_lines = !settings.filter.redact.is_empty();
+    let (mut inbound_decoder, mut outbound_decoder) =
+        decode::pair(conn.route.protocol, whole_lines);
+    let (own_decoder, peer_decoder) = match sends {
+        Direction::Inbound => (&mut inbound_decoder, &mut outbound_decoder),
+        Direction::Outbound => (&mut outbound_decoder, &mut inbound_decoder),
+    };
+    // an empty `buf` prints what the decoder held back, see `print_chunk`
+    let log = |decoder: &mut StreamDecoder, direction: Direction, buf: &[u8]| {
+        let (counter, hidden, mark) = match direction {
+            Direction::Inbound => (&counters.inbound, settings.without_inbound, ">"),
+            Direction::Outbound => (&counters.outbound, settings.without_outbound, "<"),
+        };
+        let offset = counter.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        if !hidden {
+            print_chunk(conn, settings, decoder, mark, buf, offset);
+        }
+    };
+
+    let receive = async {
+        let mut buf = vec![0; 8192];
+        let mut received = 0;
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                log(peer_decoder, sends.other(), &[]);
+                received_tx.send_replace(u64::MAX);
+                return Ok::<_, io::Error>(());
+            }
+            log(peer_decoder, sends.other(), &buf[..n]);
+            received += n as u64;
+            received_tx.send_replace(received);
+        }
+    };
+
+    let send = async {
+        let mut previous = chunks.first().map_or(0.0, |chunk| chunk.timestamp);
+        // bytes received when we last sent
+        let mut mark = 0;
+        for chunk in chunks {
+            if chunk.direction == sends {
+                tokio::time::sleep(delay(chunk.timestamp - previous, speed)).await;
+                if !chunk.payload.is_empty() {
+                    log(own_decoder, sends, &chunk.payload);
+                }
+                writer.write_all(&chunk.payload).await?;
+                mark = *received_rx.borrow();
+            } else {
+                while *received_rx.borrow_and_update() < chunk.end {
+                    match tokio::time::timeout(QUIET, received_rx.changed()).await {
+                        Ok(Ok(())) => continue,
+                        Ok(Err(_closed)) => break,
+                        Err(_elapsed) if *received_rx.borrow() > mark => break,
+                        Err(_elapsed) => continue,
+                    }
+                }
+            }
+            previous = chunk.timestamp;
+        }
+        log(own_decoder, sends, &[]);
+        writer.shutdown().await
+    };
+
+    tokio::try_join!(receive, send)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_groups_connections() {
+        let path =
+            std::env::temp_dir().join(format!("tcp-logger-replay-{}.jsonl", std::process::id()));
+        std::fs::write(
+            &path,
+            concat!(
+                r#"{"connection":2,"direction":"inbound","timestamp":1.0,"offset":0,"payload":"UElORw0K"}"#,
+                "\n",
+                r#"{"connection":1,"direction":"inbound","timestamp":1.5,"offset":0,"payload":""}"#,
+                "\n",
+                r#"{"connection":2,"direction":"outbound","timestamp":2.0,"offset":0,"payload":"K1BPTkcNCg=="}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+        let connections = load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].len(), 2);
+        assert_eq!(connections[0][0].payload, b"PING\r\n");
+        assert_eq!(connections[0][1].direction, Direction::Outbound);
+        assert_eq!(connections[0][1].end, 7);
+        assert_eq!(delay(1.0, 2.0), Duration::from_millis(500));
+        assert_eq!(delay(1.0, 0.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn speed() {
+        assert_eq!(parse_speed("0"), Ok(0.0));
+        assert_eq!(parse_speed("2.5"), Ok(2.5));
+        assert!(parse_speed("NaN").is_err());
+        assert!(parse_speed("inf").is_err());
+        assert!(parse_speed("-1").is_err());
+        assert_eq!(delay(1.0, 1e-300), Duration::MAX);
+    }
+}
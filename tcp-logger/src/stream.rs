@@ -0,0 +1,206 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+
+/// Either side of a proxied connection: plain TCP or TLS on top of it.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+/// Prefix of unix domain socket paths in `--bind` / `--server`.
+const UNIX: &str = "unix:";
+
+pub fn unix_path(addr: &str) -> Option<&str> {
+    addr.strip_prefix(UNIX)
+}
+
+/// Address of one end of a connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Addr {
+    Inet(SocketAddr),
+    /// path of the socket, clients of a unix listener are unnamed
+    Unix(String),
+}
+
+impl Addr {
+    pub fn inet(&self) -> Option<SocketAddr> {
+        match self {
+            Addr::Inet(addr) => Some(*addr),
+            Addr::Unix(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Addr::Inet(addr) => write!(f, "{}", addr),
+            Addr::Unix(path) => write!(f, "{}{}", UNIX, path),
+        }
+    }
+}
+
+/// A connected socket before TLS.
+pub enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Socket {
+    /// `addr` is `host:port` or `unix:/path`.
+    pub async fn connect(addr: &str) -> io::Result<Socket> {
+        match unix_path(addr) {
+            Some(path) => Ok(Socket::Unix(UnixStream::connect(path).await?)),
+            None => Ok(Socket::Tcp(TcpStream::connect(addr).await?)),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<Addr> {
+        match self {
+            Socket::Tcp(stream) => stream.local_addr().map(Addr::Inet),
+            Socket::Unix(stream) => Ok(unix_addr(stream.local_addr()?)),
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<Addr> {
+        match self {
+            Socket::Tcp(stream) => stream.peer_addr().map(Addr::Inet),
+            Socket::Unix(stream) => Ok(unix_addr(stream.peer_addr()?)),
+        }
+    }
+}
+
+fn unix_addr(addr: tokio::net::unix::SocketAddr) -> Addr {
+    let path = addr.as_pathname().map(|path| path.display().to_string());
+    Addr::Unix(path.unwrap_or_default())
+}
+
+impl AsyncRead for Socket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Socket::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Socket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Socket::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Socket::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Socket::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, String),
+}
+
+impl Listener {
+    /// `addr` is `host:port` or `unix:/path`. A stale socket file left over
+    /// from an earlier run is replaced, one that still accepts connections is
+    /// not.
+    pub async fn bind(addr: &str) -> io::Result<Listener> {
+        let Some(path) = unix_path(addr) else {
+            return Ok(Listener::Tcp(TcpListener::bind(addr).await?));
+        };
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => {
+                match UnixStream::connect(path).await {
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        std::fs::remove_file(path)?
+                    }
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!("{} is in use by another process", path),
+                        ))
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(Listener::Unix(UnixListener::bind(path)?, path.to_string()))
+    }
+
+    /// Clients of a unix listener are reported with the listener path.
+    pub async fn accept(&self) -> io::Result<(Socket, Addr)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((Socket::Tcp(stream), Addr::Inet(peer)))
+            }
+            Listener::Unix(listener, path) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Socket::Unix(stream), Addr::Unix(path.clone())))
+            }
+        }
+    }
+}
+
+/// Removes the socket file of a unix listener.
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn unix_socket() {
+        let path = std::env::temp_dir().join(format!("tcp-logger-{}.sock", std::process::id()));
+        let addr = format!("unix:{}", path.display());
+        // a socket file nobody listens on is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let listener = Listener::bind(&addr).await.unwrap();
+        let in_use = Listener::bind(&addr).await.err().unwrap();
+        assert_eq!(in_use.kind(), io::ErrorKind::AddrInUse);
+        // the connection that probed the socket
+        drop(listener.accept().await.unwrap());
+
+        let mut client = Socket::connect(&addr).await.unwrap();
+        let (mut server, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer.to_string(), addr);
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
@@ -19,7 +19,7 @@ impl<R: AsyncRead + Unpin, F: FnMut(&[u8])> TeeReader<R, F> {
     /// reads forwards onto the supplied reader, but performs a supplied closure
     /// on the content of that buffer at every moment of the read
     pub fn new(reader: R, f: F) -> TeeReader<R, F> {
-        TeeReader { reader: reader, f }
+        TeeReader { reader, f }
     }
 
     // / Consumes the `TeeReader`, returning the wrapped reader
@@ -34,8 +34,9 @@ impl<R: AsyncRead + Unpin, F: FnMut(&[u8])> AsyncRead for TeeReader<R, F> {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<()>> {
+        let before = buf.filled().len();
         ready!(Pin::new(&mut self.reader).poll_read(cx, buf))?;
-        (self.f)(&buf.filled());
+        (self.f)(&buf.filled()[before..]);
         Poll::Ready(Ok(()))
     }
 }
@@ -45,14 +46,14 @@ mod tests {
     use super::*;
     use tokio::io::AsyncReadExt;
 
-    #[test]
-    fn tee() {
+    #[tokio::test]
+    async fn tee() {
         let mut reader = "It's over 9000!".as_bytes();
         let mut altout: Vec<u8> = Vec::new();
         let mut teeout = Vec::new();
         {
             let mut tee = TeeReader::new(&mut reader, |bytes| altout.extend(bytes));
-            let _ = tee.read_to_end(&mut teeout);
+            tee.read_to_end(&mut teeout).await.unwrap();
         }
         assert_eq!(teeout, altout);
     }
@@ -0,0 +1,192 @@
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme};
+use std::error::Error;
+use std::sync::Arc;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Server side: terminate TLS from clients with the given certificate, or a
+/// self-signed one for `hostnames` when none is given.
+pub fn acceptor(
+    cert: Option<&str>,
+    key: Option<&str>,
+    hostnames: &[String],
+) -> Result<TlsAcceptor, Box<dyn Error>> {
+    let (certs, key) = match (cert, key) {
+        (Some(cert), Some(key)) => (
+            CertificateDer::pem_file_iter(cert)?.collect::<Result<Vec<_>, _>>()?,
+            PrivateKeyDer::from_pem_file(key)?,
+        ),
+        (None, None) => {
+            let generated = rcgen::generate_simple_self_signed(hostnames.to_vec())?;
+            println!(
+                "Generated self-signed certificate for {}",
+                hostnames.join(", ")
+            );
+            (
+                vec![generated.cert.der().clone()],
+                PrivatePkcs8KeyDer::from(generated.signing_key.serialize_der()).into(),
+            )
+        }
+        _ => return Err("--tls-cert and --tls-key must be given together".into()),
+    };
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Client side: verify the upstream against the bundled web PKI roots plus
+/// `ca`, or not at all when `insecure` is set.
+pub fn connector(ca: Option<&str>, insecure: bool) -> Result<TlsConnector, Box<dyn Error>> {
+    let builder =
+        ClientConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+    let config = if insecure {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification))
+            .with_no_client_auth()
+    } else {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        if let Some(ca) = ca {
+            for cert in CertificateDer::pem_file_iter(ca)? {
+                roots.add(cert?)?;
+            }
+        }
+        builder.with_root_certificates(roots).with_no_client_auth()
+    };
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Name sent as SNI and verified against the upstream certificate.
+pub fn server_name(name: &str) -> Result<ServerName<'static>, Box<dyn Error + Send + Sync>> {
+    Ok(ServerName::try_from(name.to_string())?)
+}
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+#[derive(Debug)]
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Accepts one TLS connection and echoes four bytes.
+    async fn echo(acceptor: TlsAcceptor) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            if let Ok(mut tls) = acceptor.accept(tcp).await {
+                let mut buf = [0; 4];
+                tls.read_exact(&mut buf).await.unwrap();
+                tls.write_all(&buf).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    async fn ping(connector: &TlsConnector, addr: SocketAddr) -> std::io::Result<[u8; 4]> {
+        let tcp = TcpStream::connect(addr).await?;
+        let mut tls = connector
+            .connect(server_name("localhost").unwrap(), tcp)
+            .await?;
+        tls.write_all(b"ping").await?;
+        let mut buf = [0; 4];
+        tls.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    fn pem(label: &str, der: &[u8]) -> String {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(der);
+        let mut pem = format!("-----BEGIN {}-----\n", label);
+        for line in encoded.as_bytes().chunks(64) {
+            pem.push_str(std::str::from_utf8(line).unwrap());
+            pem.push('\n');
+        }
+        pem + &format!("-----END {}-----\n", label)
+    }
+
+    #[tokio::test]
+    async fn self_signed() {
+        let acceptor = || acceptor(None, None, &["localhost".to_string()]).unwrap();
+        let insecure = connector(None, true).unwrap();
+        assert_eq!(
+            &ping(&insecure, echo(acceptor()).await).await.unwrap(),
+            b"ping"
+        );
+
+        let verifying = connector(None, false).unwrap();
+        assert!(ping(&verifying, echo(acceptor()).await).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn given_certificate_and_ca() {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir();
+        let cert = dir.join(format!("tcp-logger-{}.crt", std::process::id()));
+        let key = dir.join(format!("tcp-logger-{}.key", std::process::id()));
+        std::fs::write(&cert, pem("CERTIFICATE", generated.cert.der())).unwrap();
+        std::fs::write(
+            &key,
+            pem("PRIVATE KEY", &generated.signing_key.serialize_der()),
+        )
+        .unwrap();
+        let (cert, key) = (cert.to_str().unwrap(), key.to_str().unwrap());
+
+        assert!(acceptor(Some(cert), None, &[]).is_err());
+        let acceptor = acceptor(Some(cert), Some(key), &[]).unwrap();
+        let trusting = connector(Some(cert), false).unwrap();
+        let result = ping(&trusting, echo(acceptor).await).await;
+        std::fs::remove_file(cert).unwrap();
+        std::fs::remove_file(key).unwrap();
+        assert_eq!(&result.unwrap(), b"ping");
+    }
+}
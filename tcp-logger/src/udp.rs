@@ -0,0 +1,368 @@
+use crate::capture::{ConnectionCapture, Direction};
+use crate::decode::{self, Protocol, StreamDecoder};
+use crate::stream::{unix_path, Addr};
+use crate::{print_chunk, Connection, Counters, Route, Settings};
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::{lookup_host, UdpSocket};
+
+/// Largest UDP payload.
+const MAX_DATAGRAM: usize = 65535;
+
+/// One client address and the socket connected to the upstream on its behalf.
+struct Session {
+    conn: Connection,
+    client: SocketAddr,
+    counters: Counters,
+    capture: Option<ConnectionCapture>,
+    /// only hold back partial lines for `--redact`, datagrams are not decoded
+    inbound_decoder: Mutex<StreamDecoder>,
+    outbound_decoder: Mutex<StreamDecoder>,
+    upstream: UdpSocket,
+    started: Instant,
+    /// updated while the session table is locked, see `relay`
+    last_active: Mutex<Instant>,
+}
+
+type Sessions = Arc<Mutex<HashMap<SocketAddr, Arc<Session>>>>;
+
+pub struct Limits {
+    /// sessions without datagrams in either direction for this long are dropped
+    pub idle_timeout: Duration,
+    /// datagrams of new clients are dropped while this many sessions are open
+    pub max_sessions: usize,
+}
+
+/// Upstream addresses of a mapping, resolved once at startup so that new
+/// sessions do not wait for DNS in the receive loop.
+pub async fn resolve(route: &Route) -> Result<HashMap<String, SocketAddr>, Box<dyn Error>> {
+    if route.upstreams.len() > 1 {
+        return Err("--udp supports a single server per mapping".into());
+    }
+    if route.protocol != Protocol::Raw {
+        return Err("--udp does not support protocol decoders".into());
+    }
+    let mut resolved = HashMap::new();
+    for upstream in route.upstreams.candidates() {
+        if unix_path(&upstream.addr).is_some() {
+            return Err("--udp does not support unix sockets".into());
+        }
+        let addr = lookup_host(&upstream.addr)
+            .await?
+            .next()
+            .ok_or_else(|| format!("{} did not resolve", upstream.addr))?;
+        resolved.insert(upstream.addr.clone(), addr);
+    }
+    Ok(resolved)
+}
+
+/// Forwards the datagrams of one mapping. Each client gets its own upstream
+/// socket so replies can be routed back. `upstreams` are the addresses from
+/// `resolve`.
+pub async fn serve(
+    socket: UdpSocket,
+    route: Arc<Route>,
+    upstreams: HashMap<String, SocketAddr>,
+    settings: Arc<Settings>,
+    next_id: Arc<AtomicU64>,
+    limits: Limits,
+) {
+    let socket = Arc::new(socket);
+    let sessions: Sessions = Default::default();
+    let mut buf = vec![0; MAX_DATAGRAM];
+    // only the first datagram dropped for the session limit is logged
+    let mut dropping = false;
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                println!("Failed to receive datagram; error={}", e);
+                continue;
+            }
+        };
+        let (existing, open) = {
+            let sessions = sessions.lock().unwrap();
+            let session = sessions.get(&peer).cloned();
+            if let Some(session) = session.as_ref() {
+                *session.last_active.lock().unwrap() = Instant::now();
+            }
+            (session, sessions.len())
+        };
+        let session = match existing {
+            Some(session) => session,
+            None if open >= limits.max_sessions => {
+                if !dropping {
+                    println!(
+                        "UDP session limit of {} reached, dropping datagrams of new clients",
+                        limits.max_sessions
+                    );
+                    dropping = true;
+                }
+                continue;
+            }
+            None => {
+                let conn = Connection {
+                    route: route.clone(),
+                    id: next_id.fetch_add(1, Ordering::Relaxed) + 1,
+                    peer: Addr::Inet(peer),
+                };
+                println!("{} open", conn);
+                // datagrams cannot tell whether an upstream is up, take the first candidate
+                let server = route
+                    .upstreams
+                    .candidates()
+                    .next()
+                    .and_then(|upstream| upstreams.get(&upstream.addr));
+                let upstream = match server {
+                    Some(&server) => connect(server).await,
+                    None => Err("upstream was not resolved".into()),
+                };
+                let upstream = match upstream {
+                    Ok(upstream) => upstream,
+                    Err(e) => {
+                        println!("{} error error={}", conn, e);
+                        continue;
+                    }
+                };
+                dropping = false;
+                let capture = match (settings.capture.as_ref(), upstream.peer_addr()) {
+                    (Some(capture), Ok(server)) => Some(capture.session(conn.id, peer, server)),
+                    _ => None,
+                };
+                let (inbound_decoder, outbound_decoder) =
+                    decode::pair(Protocol::Raw, !settings.filter.redact.is_empty());
+                let session = Arc::new(Session {
+                    conn,
+                    client: peer,
+                    counters: Counters::default(),
+                    capture,
+                    inbound_decoder: Mutex::new(inbound_decoder),
+                    outbound_decoder: Mutex::new(outbound_decoder),
+                    upstream,
+                    started: Instant::now(),
+                    last_active: Mutex::new(Instant::now()),
+                });
+                sessions.lock().unwrap().insert(peer, session.clone());
+                tokio::spawn(relay(
+                    session.clone(),
+                    socket.clone(),
+                    sessions.clone(),
+                    settings.clone(),
+                    limits.idle_timeout,
+                ));
+                session
+            }
+        };
+
+        log(&session, &settings, Direction::Inbound, &buf[..len]);
+        if let Err(e) = session.upstream.send(&buf[..len]).await {
+            println!("{} error sending datagram; error={}", session.conn, e);
+        }
+    }
+}
+
+/// Binding to an ephemeral port and connecting a UDP socket do not wait for
+/// the network.
+async fn connect(server: SocketAddr) -> Result<UdpSocket, Box<dyn Error>> {
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse()?,
+        SocketAddr::V6(_) => "[::]:0".parse()?,
+    };
+    let upstream = UdpSocket::bind(local).await?;
+    upstream.connect(server).await?;
+    Ok(upstream)
+}
+
+/// Sends replies of the upstream back to the client until the session is idle.
+async fn relay(
+    session: Arc<Session>,
+    socket: Arc<UdpSocket>,
+    sessions: Sessions,
+    settings: Arc<Settings>,
+    idle_timeout: Duration,
+) {
+    let mut buf = vec![0; MAX_DATAGRAM];
+    let result = loop {
+        let idle = session.last_active.lock().unwrap().elapsed();
+        let received = match idle_timeout.checked_sub(idle) {
+            Some(remaining) => {
+                tokio::time::timeout(remaining, session.upstream.recv(&mut buf)).await
+            }
+            None => {
+                // the table lock keeps `serve` from picking the session meanwhile
+                let mut sessions = sessions.lock().unwrap();
+                if session.last_active.lock().unwrap().elapsed() < idle_timeout {
+                    continue;
+                }
+                sessions.remove(&session.client);
+                break Ok(());
+            }
+        };
+        let len = match received {
+            Ok(Ok(len)) => len,
+            Ok(Err(e)) => {
+                sessions.lock().unwrap().remove(&session.client);
+                break Err(e);
+            }
+            Err(_elapsed) => continue,
+        };
+        *session.last_active.lock().unwrap() = Instant::now();
+        log(&session, &settings, Direction::Outbound, &buf[..len]);
+        if let Err(e) = socket.send_to(&buf[..len], session.client).await {
+            println!("{} error sending datagram; error={}", session.conn, e);
+        }
+    };
+
+    finish(&session, &settings);
+    let summary = format!(
+        "duration={:?} inbound={} outbound={}",
+        session.started.elapsed(),
+        session.counters.inbound.load(Ordering::Relaxed),
+        session.counters.outbound.load(Ordering::Relaxed),
+    );
+    match result {
+        Ok(()) => println!("{} close {}", session.conn, summary),
+        Err(e) => println!("{} error {} error={}", session.conn, summary, e),
+    }
+}
+
+/// Each datagram is printed on its own, protocol decoders do not apply.
+fn log(session: &Session, settings: &Settings, direction: Direction, buf: &[u8]) {
+    let (counter, decoder, hidden, mark) = match direction {
+        Direction::Inbound => (
+            &session.counters.inbound,
+            &session.inbound_decoder,
+            settings.without_inbound,
+            ">",
+        ),
+        Direction::Outbound => (
+            &session.counters.outbound,
+            &session.outbound_decoder,
+            settings.without_outbound,
+            "<",
+        ),
+    };
+    let offset = counter.fetch_add(buf.len() as u64, Ordering::Relaxed);
+    if let Some(capture) = session.capture.as_ref() {
+        capture.chunk(direction, buf);
+    }
+    if hidden {
+        return;
+    }
+    let mut decoder = decoder.lock().unwrap();
+    print_chunk(&session.conn, settings, &mut decoder, mark, buf, offset);
+}
+
+/// Prints the partial lines held back for `--redact` once the session ended.
+fn finish(session: &Session, settings: &Settings) {
+    if !settings.without_inbound {
+        let offset = session.counters.inbound.load(Ordering::Relaxed);
+        let mut decoder = session.inbound_decoder.lock().unwrap();
+        print_chunk(&session.conn, settings, &mut decoder, ">", &[], offset);
+    }
+    if !settings.without_outbound {
+        let offset = session.counters.outbound.load(Ordering::Relaxed);
+        let mut decoder = session.outbound_decoder.lock().unwrap();
+        print_chunk(&session.conn, settings, &mut decoder, "<", &[], offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fault::Faults;
+    use crate::filter::Filter;
+    use crate::render::Format;
+    use crate::upstream::{Balance, Connect, Upstreams};
+
+    async fn client(proxy: SocketAddr) -> UdpSocket {
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(proxy).await.unwrap();
+        client
+    }
+
+    async fn echoed(client: &UdpSocket, payload: &[u8]) -> Option<Vec<u8>> {
+        client.send(payload).await.unwrap();
+        let mut buf = [0; 64];
+        let received = tokio::time::timeout(Duration::from_millis(200), client.recv(&mut buf));
+        let len = received.await.ok()?.unwrap();
+        Some(buf[..len].to_vec())
+    }
+
+    #[tokio::test]
+    async fn sessions() {
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 64];
+            loop {
+                let (len, from) = echo.recv_from(&mut buf).await.unwrap();
+                echo.send_to(&buf[..len], from).await.unwrap();
+            }
+        });
+
+        let connect = Connect {
+            balance: Balance::RoundRobin,
+            timeout: Duration::from_secs(1),
+            retries: 0,
+            retry_delay: Duration::ZERO,
+        };
+        let route = Arc::new(Route {
+            label: None,
+            upstreams: Upstreams::new(&echo_addr.to_string(), connect, false, None).unwrap(),
+            protocol: Protocol::Raw,
+            tls_connector: None,
+        });
+        let settings = Arc::new(Settings {
+            without_inbound: false,
+            without_outbound: false,
+            capture: None,
+            format: Format::Auto,
+            max_bytes: None,
+            tls_acceptor: None,
+            faults: Faults::default(),
+            filter: Filter::default(),
+            proxy_protocol_in: false,
+            proxy_protocol_out: None,
+        });
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let proxy = socket.local_addr().unwrap();
+        let next_id = Arc::new(AtomicU64::new(0));
+        let upstreams = resolve(&route).await.unwrap();
+        let limits = Limits {
+            idle_timeout: Duration::from_millis(600),
+            max_sessions: 2,
+        };
+        tokio::spawn(serve(
+            socket,
+            route,
+            upstreams,
+            settings,
+            next_id.clone(),
+            limits,
+        ));
+
+        // replies go back to the client that sent the request
+        let (a, b, c) = (
+            client(proxy).await,
+            client(proxy).await,
+            client(proxy).await,
+        );
+        assert_eq!(echoed(&a, b"a").await.unwrap(), b"a");
+        assert_eq!(echoed(&b, b"b").await.unwrap(), b"b");
+        assert_eq!(echoed(&a, b"a2").await.unwrap(), b"a2");
+        // a third client is over the limit
+        assert_eq!(echoed(&c, b"c").await, None);
+        assert_eq!(next_id.load(Ordering::Relaxed), 2);
+
+        // idle sessions are dropped, making room and starting new sessions
+        tokio::time::sleep(Duration::from_millis(900)).await;
+        assert_eq!(echoed(&c, b"c").await.unwrap(), b"c");
+        assert_eq!(echoed(&a, b"a3").await.unwrap(), b"a3");
+        assert_eq!(next_id.load(Ordering::Relaxed), 4);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::stream::{unix_path, Socket};
+use crate::tls;
+use clap::ValueEnum;
+use std::error::Error;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Balance {
+    /// each connection starts with the next upstream
+    RoundRobin,
+    /// always start with the first upstream, the others are fallbacks
+    FirstAvailable,
+}
+
+/// How connections to the upstreams are made, shared by all mappings.
+#[derive(Clone, Debug)]
+pub struct Connect {
+    pub balance: Balance,
+    pub timeout: Duration,
+    /// extra rounds over all upstreams after every one of them failed
+    pub retries: u32,
+    pub retry_delay: Duration,
+}
+
+pub struct Upstream {
+    pub addr: String,
+    /// SNI / verified name with `--upstream-tls`
+    pub sni: Option<ServerName<'static>>,
+}
+
+/// The upstreams of one mapping.
+pub struct Upstreams {
+    list: Vec<Upstream>,
+    connect: Connect,
+    next: AtomicUsize,
+}
+
+impl Upstreams {
+    /// `servers` is a comma separated list of `host:port` / `unix:/path`. The
+    /// SNI defaults to the host of each upstream, `localhost` for unix sockets.
+    pub fn new(
+        servers: &str,
+        connect: Connect,
+        tls: bool,
+        sni: Option<&str>,
+    ) -> Result<Upstreams, Box<dyn Error>> {
+        let mut list = Vec::new();
+        for addr in servers.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let sni = if tls {
+                let name = match unix_path(addr) {
+                    Some(_) => sni.unwrap_or("localhost"),
+                    None => sni.unwrap_or_else(|| host(addr)),
+                };
+                Some(tls::server_name(name).map_err(|e| e.to_string())?)
+            } else {
+                None
+            };
+            list.push(Upstream {
+                addr: addr.to_string(),
+                sni,
+            });
+        }
+        if list.is_empty() {
+            return Err(format!("no upstream in {:?}", servers).into());
+        }
+        Ok(Upstreams {
+            list,
+            connect,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    /// Upstreams in the order they should be tried for a new connection.
+    pub fn candidates(&self) -> impl Iterator<Item = &Upstream> {
+        let start = match self.connect.balance {
+            Balance::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % self.list.len(),
+            Balance::FirstAvailable => 0,
+        };
+        self.list.iter().cycle().skip(start).take(self.list.len())
+    }
+
+    /// Tries the candidates until one accepts, for `retries` more rounds.
+    /// `failed` is called for every failed attempt except the last one,
+    /// whose error is returned.
+    pub async fn connect(
+        &self,
+        mut failed: impl FnMut(&Upstream, &io::Error),
+    ) -> io::Result<(Socket, &Upstream)> {
+        let candidates: Vec<_> = self.candidates().collect();
+        let attempts = candidates.len() * (self.connect.retries as usize + 1);
+        let mut attempt = 0;
+        loop {
+            for &upstream in &candidates {
+                let result = match tokio::time::timeout(
+                    self.connect.timeout,
+                    Socket::connect(&upstream.addr),
+                )
+                .await
+                {
+                    Ok(result) => result,
+                    Err(_elapsed) => Err(io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!("connect to {} timed out", upstream.addr),
+                    )),
+                };
+                attempt += 1;
+                match result {
+                    Ok(stream) => return Ok((stream, upstream)),
+                    Err(e) if attempt == attempts => return Err(e),
+                    Err(e) => failed(upstream, &e),
+                }
+            }
+            tokio::time::sleep(self.connect.retry_delay).await;
+        }
+    }
+}
+
+/// Host part of `host:port` / `[v6]:port`.
+fn host(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect(balance: Balance) -> Connect {
+        Connect {
+            balance,
+            timeout: Duration::from_secs(1),
+            retries: 1,
+            retry_delay: Duration::ZERO,
+        }
+    }
+
+    fn order(upstreams: &Upstreams) -> Vec<&str> {
+        upstreams.candidates().map(|u| u.addr.as_str()).collect()
+    }
+
+    #[test]
+    fn candidates() {
+        let upstreams =
+            Upstreams::new("a:1, b:2,c:3", connect(Balance::RoundRobin), false, None).unwrap();
+        assert_eq!(order(&upstreams), ["a:1", "b:2", "c:3"]);
+        assert_eq!(order(&upstreams), ["b:2", "c:3", "a:1"]);
+
+        let upstreams =
+            Upstreams::new("a:1,b:2", connect(Balance::FirstAvailable), true, None).unwrap();
+        assert_eq!(order(&upstreams), ["a:1", "b:2"]);
+        assert_eq!(order(&upstreams), ["a:1", "b:2"]);
+        assert_eq!(upstreams.list[1].sni.as_ref().unwrap().to_str(), "b");
+        assert_eq!(host("[::1]:443"), "::1");
+        assert!(Upstreams::new(",", connect(Balance::RoundRobin), false, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn connect_falls_back() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // port 1 refuses connections
+        let servers = format!("127.0.0.1:1,{}", addr);
+        let upstreams =
+            Upstreams::new(&servers, connect(Balance::FirstAvailable), false, None).unwrap();
+        let mut failures = Vec::new();
+        let (_, upstream) = upstreams
+            .connect(|upstream, _| failures.push(upstream.addr.clone()))
+            .await
+            .unwrap();
+        assert_eq!(upstream.addr, addr.to_string());
+        assert_eq!(failures, ["127.0.0.1:1"]);
+
+        let upstreams =
+            Upstreams::new("127.0.0.1:1", connect(Balance::FirstAvailable), false, None).unwrap();
+        let mut failures = 0;
+        assert!(upstreams.connect(|_, _| failures += 1).await.is_err());
+        assert_eq!(failures, 1);
+    }
+}